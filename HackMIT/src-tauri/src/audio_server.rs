@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+// Loopback-only file server for downloaded clips, so the webview plays a local, seekable URL
+// instead of a remote one that may block CORS or expire
+pub struct AudioServer {
+    port: u16,
+    dir: PathBuf, // temp/audio; the only directory served
+    shutdown: Arc<Notify>,
+}
+
+impl AudioServer {
+    // Bind 127.0.0.1 on a free port and serve `dir` under /audio/ until shutdown()
+    pub(crate) fn start(dir: PathBuf) -> Result<Self> {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).context("bind audio server")?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let shutdown = Arc::new(Notify::new());
+        let stop = shutdown.clone();
+        let serve_dir = dir.clone();
+        tauri::async_runtime::spawn(async move {
+            let listener = match TcpListener::from_std(listener) {
+                Ok(l) => l,
+                Err(e) => {
+                    println!("Audio server failed to start: {}", e);
+                    return;
+                }
+            };
+            loop {
+                tokio::select! {
+                    _ = stop.notified() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            let dir = serve_dir.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = serve(stream, &dir).await {
+                                    println!("Audio server request failed: {:#}", e);
+                                }
+                            });
+                        }
+                        Err(e) => println!("Audio server accept failed: {}", e),
+                    },
+                }
+            }
+            println!("Audio server stopped");
+        });
+        println!("Serving downloaded audio on http://127.0.0.1:{}/audio/", port);
+        Ok(Self { port, dir, shutdown })
+    }
+
+    pub(crate) fn dir(&self) -> &Path { &self.dir }
+
+    pub(crate) fn url_for(&self, name: &str) -> String {
+        format!("http://127.0.0.1:{}/audio/{}", self.port, name)
+    }
+
+    // Stops accepting; requests already being served run to completion
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+}
+
+// Plain file names only: no separators, no leading dot, nothing outside temp/audio
+pub(crate) fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("mp3") => "audio/mpeg",
+        Some("m4a") | Some("mp4") => "audio/mp4",
+        Some("wav") => "audio/wav",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+// First range of a `Range: bytes=...` header as [start, end); None when it can't be satisfied
+fn parse_range(header: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?.split(',').next()?.trim();
+    let (from, to) = spec.split_once('-')?;
+    let (start, end) = if from.is_empty() {
+        // Suffix form: the last N bytes
+        let n: u64 = to.parse().ok()?;
+        (len.saturating_sub(n), len)
+    } else {
+        let start: u64 = from.parse().ok()?;
+        let end = if to.is_empty() { len } else { to.parse::<u64>().ok()?.saturating_add(1).min(len) };
+        (start, end)
+    };
+    (start < end).then_some((start, end))
+}
+
+async fn respond_status(out: &mut (impl AsyncWriteExt + Unpin), status: &str, extra: &str) -> Result<()> {
+    let head = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n{}Connection: close\r\n\r\n", status, extra);
+    out.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+// One request per connection: GET/HEAD /audio/<name>, with single-range support for seeking
+async fn serve(mut stream: TcpStream, dir: &Path) -> Result<()> {
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() { break; }
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case("range") { range = Some(value.trim().to_string()); }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" && method != "HEAD" {
+        return respond_status(&mut write, "405 Method Not Allowed", "Allow: GET, HEAD\r\n").await;
+    }
+    let Some(path) = target.strip_prefix("/audio/").filter(|n| is_safe_name(n)).map(|n| dir.join(n)) else {
+        return respond_status(&mut write, "404 Not Found", "").await;
+    };
+    let Ok(mut file) = tokio::fs::File::open(&path).await else {
+        return respond_status(&mut write, "404 Not Found", "").await;
+    };
+    let len = file.metadata().await?.len();
+    let (status, start, end) = match range.as_deref() {
+        None => ("200 OK", 0, len),
+        Some(r) => match parse_range(r, len) {
+            Some((start, end)) => ("206 Partial Content", start, end),
+            None => {
+                let extra = format!("Content-Range: bytes */{}\r\n", len);
+                return respond_status(&mut write, "416 Range Not Satisfiable", &extra).await;
+            }
+        },
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n",
+        status,
+        content_type(&path),
+        end - start
+    );
+    if status.starts_with("206") {
+        head.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n", start, end - 1, len));
+    }
+    head.push_str("\r\n");
+    write.write_all(head.as_bytes()).await?;
+    if method == "GET" {
+        file.seek(SeekFrom::Start(start)).await?;
+        tokio::io::copy(&mut (&mut file).take(end - start), &mut write).await?;
+    }
+    write.flush().await?;
+    Ok(())
+}
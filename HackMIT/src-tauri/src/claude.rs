@@ -4,14 +4,17 @@ use base64::Engine as _;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 #[derive(Serialize, Deserialize)]
 struct AnthropicRequest {
     model: String,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
     messages: Vec<Message>,
 }
 
@@ -42,52 +45,283 @@ struct ImageSource {
 #[derive(Serialize, Deserialize)]
 struct AnthropicResponse {
     content: Vec<ResponseContent>,
+    #[serde(default)]
+    stop_reason: Option<String>, // "refusal" when the safety filter stopped the answer
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ResponseContent {
-    text: String,
+    #[serde(rename = "type")]
+    content_type: String,
+    #[serde(default)]
+    text: Option<String>, // absent on non-text blocks (e.g. thinking)
+}
+
+impl AnthropicResponse {
+    // Join every text block; the JSON may not be in the first one
+    fn joined_text(&self) -> Option<String> {
+        let parts: Vec<&str> = self
+            .content
+            .iter()
+            .filter(|c| c.content_type == "text")
+            .filter_map(|c| c.text.as_deref())
+            .collect();
+        if parts.is_empty() { None } else { Some(parts.join("\n")) }
+    }
 }
 
 // We no longer depend on strict ClaudeResponse; we'll parse flexibly from serde_json::Value
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, schemars::JsonSchema)]
 pub struct HackmitGenerateReq {
     #[serde(skip_serializing_if = "Option::is_none")] pub topic: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")] pub tags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub negative_tags: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")] pub prompt: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")] pub make_instrumental: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")] pub cover_clip_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub reason: Option<String>, // Claude's evidence for its read of the screen; never sent to Suno
 }
 
-#[derive(Deserialize)]
-struct UserPreferences {
+// HackMIT endpoint limit on the style tags
+const MAX_TAGS_CHARS: usize = 100;
+
+impl HackmitGenerateReq {
+    // Checks a hand-written request before it is sent; Claude's output is shaped by build_hackmit_req_from_claude instead
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let filled = |v: &Option<String>| v.as_deref().is_some_and(|s| !s.trim().is_empty());
+        if !filled(&self.topic) && !filled(&self.tags) && !filled(&self.prompt) {
+            return Err("Request needs at least one of topic, tags or prompt".to_string());
+        }
+        if let Some(tags) = &self.tags {
+            let len = tags.chars().count();
+            if len > MAX_TAGS_CHARS {
+                return Err(format!("tags is {} characters, the limit is {}", len, MAX_TAGS_CHARS));
+            }
+        }
+        if self.make_instrumental == Some(false) && !filled(&self.prompt) {
+            return Err("A vocal track (make_instrumental: false) needs lyrics in prompt".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, schemars::JsonSchema)]
+pub(crate) struct UserPreferences {
     make_instrumental: Option<bool>,
+    default_instrumental: Option<bool>, // fallback when nothing else says otherwise
+    global_negative_tags: Option<Vec<String>>, // styles to avoid on every track, whatever the context
+    lyrics_language: Option<String>, // language code for lyrics; the UI's choice wins
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, schemars::JsonSchema)]
 pub struct FrontendPreferences {
     pub genres: Option<Vec<String>>, // from multi-select
     pub vocals_gender: Option<String>, // "male" | "female" | "none"
     pub instrumental: Option<bool>, // true => no lyrics
     pub silly_mode: Option<bool>, // optional extra from UI
+    pub suno_model: Option<String>, // e.g. "V4_5"; overrides request.json for the main Suno API
+    pub lyrics_language: Option<String>, // language code, e.g. "es" or "pt-BR"; English when unset
+    pub style_weight: Option<f32>, // main Suno API weights, 0-1; request.json's values when unset
+    pub weirdness_constraint: Option<f32>,
+    pub audio_weight: Option<f32>,
 }
 
-pub(crate) fn project_root() -> Result<PathBuf> {
-    // Start from current dir and walk up to folder containing package.json (HackMIT root)
-    let mut dir = std::env::current_dir()?;
-    loop {
-        if dir.join("package.json").exists() {
-            return Ok(dir);
+// Latest FrontendPreferences pushed by the UI; read by the periodic task when it generates
+#[derive(Default)]
+pub struct FrontendPrefsState(pub tokio::sync::Mutex<Option<FrontendPreferences>>);
+
+// Stores the known fields and returns warnings for the rest (unknown or misspelled keys), so a UI that is
+// newer or older than the backend keeps working
+#[tauri::command]
+pub async fn set_frontend_preferences(state: tauri::State<'_, FrontendPrefsState>, prefs: Value) -> Result<Vec<String>, String> {
+    let warnings = crate::prefs_schema::validate_preferences(prefs.to_string(), Some("frontend".to_string()))?;
+    for warning in &warnings {
+        println!("Frontend preferences: {}", warning);
+    }
+    let prefs: FrontendPreferences = serde_json::from_value(prefs).map_err(|e| format!("Invalid frontend preferences: {}", e))?;
+    if let Some(code) = prefs.lyrics_language.as_deref().filter(|c| !is_language_code(c)) {
+        return Err(format!("lyrics_language '{}' is not a language code (expected e.g. \"es\", \"fr\" or \"pt-BR\")", code));
+    }
+    *state.0.lock().await = Some(prefs);
+    Ok(warnings)
+}
+
+// Token budget and temperature for one Claude call; temperature None = Anthropic's default
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sampling {
+    pub max_tokens: u32,
+    pub temperature: Option<f32>,
+}
+
+// How screenshots are encoded for Claude; png keeps the captured file byte-for-byte
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg, // lossy, sized by image_quality
+    Webp, // lossless
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ImageUpload {
+    pub format: ImageFormat,
+    pub quality: u8, // JPEG quality 1-100
+}
+
+// suno-config/claude.json; every field is optional
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ClaudeConfig {
+    pub max_tokens: u32,                  // request generation
+    pub temperature: Option<f32>,         // request generation (instrumental)
+    pub lyrics_temperature: Option<f32>,  // request generation when lyrics are wanted; falls back to temperature
+    pub classify_max_tokens: u32,         // screenshot classification
+    pub classify_temperature: Option<f32>,
+    pub classify_context_chars: usize,    // cap on context text (hints, blended contexts, window titles) injected into the prompt
+    pub classify_timeout_secs: u64,       // limit on the screenshot call; a repair or topic rewrite is separate
+    pub image_format: ImageFormat,        // screenshot encoding sent to Claude
+    pub image_quality: u8,                // JPEG quality, 1-100
+    pub model_rates: BTreeMap<String, ModelRate>, // USD per million tokens by model, for get_spend_summary
+    pub retry_topic_length: bool,         // re-ask once for a topic outside 400-499 chars
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct ModelRate {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+fn default_model_rates() -> BTreeMap<String, ModelRate> {
+    BTreeMap::from([
+        ("claude-3-5-haiku-latest".to_string(), ModelRate { input_per_mtok: 0.80, output_per_mtok: 4.0 }),
+        ("claude-3-haiku-20240307".to_string(), ModelRate { input_per_mtok: 0.25, output_per_mtok: 1.25 }),
+    ])
+}
+
+impl Default for ClaudeConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 2000,
+            temperature: None,
+            lyrics_temperature: None,
+            classify_max_tokens: 300,
+            classify_temperature: None,
+            classify_context_chars: 1500,
+            classify_timeout_secs: 45,
+            image_format: ImageFormat::Png,
+            image_quality: 80,
+            model_rates: default_model_rates(),
+            retry_topic_length: false,
+        }
+    }
+}
+
+impl ClaudeConfig {
+    pub(crate) fn generation(&self, instrumental: bool) -> Sampling {
+        let temperature = if instrumental { self.temperature } else { self.lyrics_temperature.or(self.temperature) };
+        Sampling { max_tokens: self.max_tokens, temperature }
+    }
+
+    pub(crate) fn classification(&self) -> Sampling {
+        Sampling { max_tokens: self.classify_max_tokens, temperature: self.classify_temperature }
+    }
+
+    pub(crate) fn upload(&self) -> ImageUpload {
+        ImageUpload { format: self.image_format, quality: self.image_quality }
+    }
+}
+
+// Keep the start of `text` within `max_chars`, ending in an ellipsis when cut
+pub(crate) fn cap_context_text(text: &str, max_chars: usize) -> String {
+    let len = text.chars().count();
+    if len <= max_chars { return text.to_string(); }
+    println!("Truncating injected context from {} to {} chars", len, max_chars);
+    let mut capped: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    capped.push('…');
+    capped
+}
+
+pub(crate) fn load_claude_config(root: &Path) -> Result<ClaudeConfig> {
+    let path = root.join("suno-config").join("claude.json");
+    let cfg: ClaudeConfig = match fs::read_to_string(&path) {
+        Ok(txt) => serde_json::from_str(&txt).with_context(|| format!("Invalid {}", path.display()))?,
+        Err(_) => ClaudeConfig::default(),
+    };
+    check_claude_config(&cfg)?;
+    Ok(cfg)
+}
+
+// Range checks serde can't express
+pub(crate) fn check_claude_config(cfg: &ClaudeConfig) -> Result<()> {
+    for (name, t) in [("temperature", cfg.temperature), ("lyrics_temperature", cfg.lyrics_temperature), ("classify_temperature", cfg.classify_temperature)] {
+        if let Some(t) = t {
+            if !(0.0..=1.0).contains(&t) { anyhow::bail!("{} in claude.json must be within 0.0-1.0, got {}", name, t); }
         }
-        if !dir.pop() { break; }
     }
-    anyhow::bail!("Could not locate project root with package.json")
+    Ok(())
+}
+
+// How project_root found the directory holding suno-config/ and temp/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RootStrategy {
+    PackageJson, // nearest ancestor of the working directory with package.json (the repo layout)
+    DataDirEnv,  // HACKMIT_DATA_DIR
+    CurrentDir,
+    ExeDir,      // directory of the running executable
+}
+
+pub(crate) fn locate_project_root() -> Result<(PathBuf, RootStrategy)> {
+    let cwd = std::env::current_dir().ok();
+    if let Some(dir) = cwd.as_deref().and_then(|c| c.ancestors().find(|d| d.join("package.json").exists())) {
+        return Ok((dir.to_path_buf(), RootStrategy::PackageJson));
+    }
+    // Outside the repo (installed app, CI): explicit data dir, then wherever we were started
+    if let Some(dir) = std::env::var_os("HACKMIT_DATA_DIR").filter(|v| !v.is_empty()) {
+        return Ok((PathBuf::from(dir), RootStrategy::DataDirEnv));
+    }
+    if let Some(dir) = cwd {
+        return Ok((dir, RootStrategy::CurrentDir));
+    }
+    let exe = std::env::current_exe().context("Could not locate project root: no working directory or executable path")?;
+    let dir = exe.parent().context("Executable has no parent directory")?;
+    Ok((dir.to_path_buf(), RootStrategy::ExeDir))
+}
+
+pub(crate) fn project_root() -> Result<PathBuf> {
+    static LOGGED: std::sync::Once = std::sync::Once::new();
+    let (root, strategy) = locate_project_root()?;
+    LOGGED.call_once(|| println!("Project root {} ({:?})", root.display(), strategy));
+    Ok(root)
+}
+
+// Precedence for make_instrumental: frontend > preferences file > default_instrumental > true
+fn resolve_instrumental(preferences: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>) -> bool {
+    if let Some(instr) = fe_prefs.as_ref().and_then(|fp| fp.instrumental) {
+        return instr;
+    }
+    if let Some(p) = preferences {
+        if let Some(instr) = p.make_instrumental { return instr; }
+        if let Some(instr) = p.default_instrumental { return instr; }
+    }
+    true
 }
 
+// Newest screenshot that is completely written: a capture being written (or one cut short) is
+// skipped in favour of the next-newest. Equal mtimes (coarse filesystem timestamps) go to the
+// lexicographically last file name, so the pick doesn't depend on directory order.
 fn find_latest_screenshot(temp_dir: &Path) -> Result<PathBuf> {
-    let mut latest: Option<(PathBuf, SystemTime)> = None;
     if !temp_dir.exists() { anyhow::bail!("temp directory not found: {}", temp_dir.display()); }
+    let mut candidates: Vec<(PathBuf, SystemTime)> = Vec::new();
     for entry in fs::read_dir(temp_dir)? {
         let entry = entry?;
         let path = entry.path();
@@ -95,68 +329,364 @@ fn find_latest_screenshot(temp_dir: &Path) -> Result<PathBuf> {
             if matches!(ext.to_ascii_lowercase().as_str(), "png" | "jpg" | "jpeg") {
                 let meta = entry.metadata()?;
                 let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-                match &latest {
-                    Some((_, t)) if mtime <= *t => {}
-                    _ => latest = Some((path.clone(), mtime)),
-                }
+                candidates.push((path, mtime));
             }
         }
     }
-    latest.map(|(p, _)| p).ok_or_else(|| anyhow::anyhow!("No screenshots found in {}", temp_dir.display()))
+    if candidates.is_empty() { anyhow::bail!("No screenshots found in {}", temp_dir.display()); }
+    candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.file_name().cmp(&a.0.file_name())));
+    for (path, _) in &candidates {
+        if is_complete_image(path) { return Ok(path.clone()); }
+        println!("Skipping incomplete screenshot {}", path.display());
+    }
+    anyhow::bail!("No complete screenshots in {} ({} incomplete)", temp_dir.display(), candidates.len())
+}
+
+// Cheap check without decoding the pixels: the header parses and the file ends where the format
+// says it should (PNG IEND chunk, JPEG end-of-image marker), which a truncated write doesn't
+fn is_complete_image(path: &Path) -> bool {
+    let Ok(reader) = image::io::Reader::open(path).and_then(|r| r.with_guessed_format()) else { return false };
+    let format = reader.format();
+    if reader.into_dimensions().is_err() { return false; }
+    let Some(tail) = file_tail(path, 8) else { return false };
+    match format {
+        Some(image::ImageFormat::Png) => tail.ends_with(&[0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82]),
+        Some(image::ImageFormat::Jpeg) => tail.ends_with(&[0xFF, 0xD9]),
+        _ => false,
+    }
+}
+
+fn file_tail(path: &Path, n: u64) -> Option<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(n))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    Some(tail)
+}
+
+fn global_negative_tags(preferences: &Option<UserPreferences>) -> Vec<String> {
+    preferences
+        .as_ref()
+        .and_then(|p| p.global_negative_tags.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
 }
 
 fn load_user_preferences(root: &Path) -> Option<UserPreferences> {
     let prefs_path = root.join("sample_preferences.json");
-    let txt = fs::read_to_string(prefs_path).ok()?;
-    serde_json::from_str(&txt).ok()
+    let txt = fs::read_to_string(&prefs_path).ok()?;
+    // Unknown or misspelled keys are reported but don't cost the user the settings that did load
+    for problem in crate::prefs_schema::validate_preferences(txt.clone(), Some("user".to_string())).unwrap_or_default() {
+        println!("{}: {}", prefs_path.display(), problem);
+    }
+    match serde_json::from_str(&txt) {
+        Ok(p) => Some(p),
+        Err(e) => {
+            println!("Ignoring {}: {}", prefs_path.display(), e);
+            None
+        }
+    }
+}
+
+const DEFAULT_LYRICS_LANGUAGE: &str = "en";
+
+// BCP 47 shape: a 2-3 letter language, then optional 2-8 character subtags ("pt-BR", "zh-Hant")
+pub(crate) fn is_language_code(code: &str) -> bool {
+    let mut parts = code.split('-');
+    let primary = parts.next().unwrap_or("");
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|p| (2..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+// The UI's choice, then sample_preferences.json, then English; invalid codes are skipped
+fn lyrics_language(preferences: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>) -> String {
+    let fe = fe_prefs.as_ref().and_then(|fp| fp.lyrics_language.as_deref());
+    let user = preferences.as_ref().and_then(|p| p.lyrics_language.as_deref());
+    for code in [fe, user].into_iter().flatten() {
+        if is_language_code(code) { return code.to_string(); }
+        println!("Ignoring invalid lyrics_language '{}'", code);
+    }
+    DEFAULT_LYRICS_LANGUAGE.to_string()
+}
+
+fn is_english(code: &str) -> bool {
+    code.split('-').next().is_some_and(|p| p.eq_ignore_ascii_case("en"))
+}
+
+// English name for the common codes, so the prompt reads naturally; the code itself otherwise
+fn language_name(code: &str) -> String {
+    let name = match code.split('-').next().unwrap_or("").to_ascii_lowercase().as_str() {
+        "en" => "English",
+        "es" => "Spanish",
+        "fr" => "French",
+        "de" => "German",
+        "it" => "Italian",
+        "pt" => "Portuguese",
+        "nl" => "Dutch",
+        "ja" => "Japanese",
+        "ko" => "Korean",
+        "zh" => "Chinese",
+        "hi" => "Hindi",
+        "ar" => "Arabic",
+        "ru" => "Russian",
+        "tr" => "Turkish",
+        "pl" => "Polish",
+        "sv" => "Swedish",
+        _ => return format!("the language with code '{}'", code),
+    };
+    format!("{} ({})", name, code)
+}
+
+// Lyrics instruction for non-English languages; English prompts are left as they were
+fn language_rule(language: &str) -> String {
+    if is_english(language) { return String::new(); }
+    format!(
+        "\n\nLYRICS LANGUAGE:\n- Write lyrics in {}. Every line of the 'prompt' field must be in that language.\n- Keep topic, tags and negative_tags in English (they describe the music, not the words).\n",
+        language_name(language)
+    )
+}
+
+// How each work state should steer the music; suno-config/intent_map.json replaces these
+const DEFAULT_INTENTS: [(&str, &str); 6] = [
+    ("High cognitive load (complex tasks)", "Simpler, less distracting music"),
+    ("Low cognitive load (routine tasks)", "More engaging, dynamic music"),
+    ("Creative tasks", "Inspiring, flowing music"),
+    ("Analytical tasks", "Structured, minimal music"),
+    ("Overwhelmed state", "Calming, grounding music"),
+    ("Focused state", "Steady, supportive music"),
+];
+
+fn intent_map_path(root: &Path) -> PathBuf { root.join("suno-config").join("intent_map.json") }
+
+fn default_intents() -> Vec<(String, String)> {
+    DEFAULT_INTENTS.iter().map(|(state, music)| (state.to_string(), music.to_string())).collect()
+}
+
+// intent_map.json is { "<work state>": "<musical direction>" }; missing, malformed or empty => the defaults
+fn load_intent_map(root: &Path) -> Vec<(String, String)> {
+    let path = intent_map_path(root);
+    let Ok(txt) = fs::read_to_string(&path) else { return default_intents() };
+    match serde_json::from_str::<BTreeMap<String, String>>(&txt) {
+        Ok(map) => {
+            let intents: Vec<(String, String)> = map
+                .into_iter()
+                .map(|(state, music)| (state.trim().to_string(), music.trim().to_string()))
+                .filter(|(state, music)| !state.is_empty() && !music.is_empty())
+                .collect();
+            if intents.is_empty() {
+                println!("{} has no usable entries, using the default intents", path.display());
+                return default_intents();
+            }
+            intents
+        }
+        Err(e) => {
+            println!("Invalid {}: {}, using the default intents", path.display(), e);
+            default_intents()
+        }
+    }
+}
+
+fn intent_lines(intents: &[(String, String)]) -> String {
+    intents.iter().map(|(state, music)| format!("   - {} → {}\n", state, music)).collect()
 }
 
-fn build_prompt(preferences: &Option<UserPreferences>, recent_genres: &[String], fe_prefs: &Option<FrontendPreferences>) -> String {
+fn build_prompt(preferences: &Option<UserPreferences>, recent_genres: &[RecentGenre], fe_prefs: &Option<FrontendPreferences>, pin: Option<&GenrePin>, seed: Option<u64>, intents: &[(String, String)]) -> String {
     let preferences_context = match preferences {
-        Some(p) => format!("\n\nPRIMARY FACTOR - USER PREFERENCES (equal weight with screenshot context):\nUser prefers instrumental: {}\n", p.make_instrumental.unwrap_or(true)),
+        Some(_) => format!("\n\nPRIMARY FACTOR - USER PREFERENCES (equal weight with screenshot context):\nUser prefers instrumental: {}\n", resolve_instrumental(preferences, &None)),
         None => String::new(),
     };
+    let vetoed = global_negative_tags(preferences);
+    let negative_context = if vetoed.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "\n\nALWAYS AVOID (user's permanent veto, applies to every context):\n- negative_tags MUST include: {}\n- Never steer the music toward these styles, even if the screenshot suggests them.\n",
+            vetoed.join(", ")
+        )
+    };
 
     let fe_context = if let Some(fp) = fe_prefs {
         let genres = fp.genres.clone().unwrap_or_default().join(", ");
         let vocals = fp.vocals_gender.clone().unwrap_or_else(|| "none".to_string());
-        let instr = fp.instrumental.unwrap_or(true);
+        let instr = resolve_instrumental(preferences, fe_prefs);
         let silly = fp.silly_mode.unwrap_or(false);
     let lyric_style = if instr { "N/A (instrumental)" } else if silly { "SILLY / HUMOROUS (funny, witty, light)" } else { "SERIOUS / PROFESSIONAL (natural, singable, appealing)" };
     format!("\n\nEXPLICIT FRONTEND PREFERENCES (highest priority):\n- Selected genres: {}\n- Instrumental: {}\n- Vocal gender preference: {} (if instrumental=false)\n- Lyrics style: {}\nRULES FOR LYRICS (when instrumental=false):\n- You MUST provide coherent, natural, singable lyrics in the 'prompt' field (multi-line text).\n- No character limit for lyrics; write as long as needed to make sense.\n- If SILLY, be playful and witty; reference what's on the screen or the user's task if appropriate.\n- If SERIOUS, write genuine, professional-sounding lyrics that fit the chosen genre; not necessarily tied to the task.\n- Keep it clean and safe.\n", genres, instr, vocals, lyric_style)
     } else { String::new() };
 
-    let diversity_guidance = {
+    // A pinned genre replaces the anti-repetition rules for as long as it's active
+    let diversity_guidance = if let Some(pin) = pin {
+        format!(
+            "\n\nPINNED GENRE (user override, very important):\n- The user pinned '{}'. The primary genre (first tag) MUST be '{}'.\n- Repeating this genre is intended; vary mood, tempo and instrumentation within it instead.\n",
+            pin.genre, pin.genre
+        )
+    } else {
         let recent = if recent_genres.is_empty() {
             "(none)".to_string()
         } else {
-            recent_genres.join(", ")
+            recent_genres.iter().map(|g| g.label()).collect::<Vec<_>>().join(", ")
+        };
+        let instrumental = resolve_instrumental(preferences, fe_prefs);
+        let mode = if instrumental { "instrumental" } else { "vocal" };
+        // Seeded sessions name the next genre outright so the same starting state gives the same progression
+        let seeded = match seed {
+            Some(seed) => format!(
+                "- Use '{}' as the primary genre (first tag) for this track unless the screenshot context strongly requires something else.\n",
+                seeded_genre(seed, recent_genres, instrumental)
+            ),
+            None => String::new(),
         };
         format!(
-            "\n\nGENRE DIVERSITY RULES (very important):\n- Recent primary genres used (most recent first): {}\n- DO NOT repeat the same primary genre within the last 3 tracks unless the screenshot context strongly requires it.\n- If recent contained 'ambient' or 'electronic', choose a different non-electronic genre now (e.g., classical/orchestral, pop, rock, heavy metal, jazz, hip hop, acoustic, lofi, folk, blues, world).\n- If instrumental is preferred, still vary genre (e.g., orchestral/classical, acoustic fingerstyle, post-rock instrumental, jazz trio, string quartet).\n- Provide 2–4 concise tags including the primary GENRE first (e.g., 'classical, orchestral, cinematic' or 'rock, post-rock, guitar-driven').\n",
-            recent
+            "\n\nGENRE DIVERSITY RULES (very important):\n- Recent primary genres used (most recent first): {}\n- This track will be {}. A genre only counts as a repeat if it was used in the same mode (instrumental vs vocal); entries marked 'unknown' count as a repeat in either mode.\n- DO NOT repeat the same primary genre within the last 3 tracks unless the screenshot context strongly requires it.\n- If recent contained 'ambient' or 'electronic', choose a different non-electronic genre now (e.g., classical/orchestral, pop, rock, heavy metal, jazz, hip hop, acoustic, lofi, folk, blues, world).\n- If instrumental is preferred, still vary genre (e.g., orchestral/classical, acoustic fingerstyle, post-rock instrumental, jazz trio, string quartet).\n{}- Provide 2–4 concise tags including the primary GENRE first (e.g., 'classical, orchestral, cinematic' or 'rock, post-rock, guitar-driven').\n",
+            recent, mode, seeded
         )
     };
 
     format!(
-        "CRITICAL: Analyze this screenshot and user preferences as EQUAL PRIMARY factors, then use cognitive load analysis to fine-tune the music generation.\n\nPRIMARY ANALYSIS (Equal Priority):\nSCREENSHOT CONTEXT:\n1. What application/website is the user actively using?\n2. What specific task are they performing right now?\n3. What is their current work state (focused, overwhelmed, creative, analytical)?\n4. What type of cognitive load are they experiencing?\n\nUSER PREFERENCES:\n5. What are the user's preferred genres, instruments, and artists?\n6. What energy level and mood do they prefer?\n7. What should be avoided based on their preferences?\n\nCOGNITIVE LOAD & CONTEXT REFINEMENT:\n8. Based on the cognitive load analysis, how should the music be adjusted?\n   - High cognitive load (complex tasks) → Simpler, less distracting music\n   - Low cognitive load (routine tasks) → More engaging, dynamic music\n   - Creative tasks → Inspiring, flowing music\n   - Analytical tasks → Structured, minimal music\n   - Overwhelmed state → Calming, grounding music\n   - Focused state → Steady, supportive music\n\nGenerate a complete Suno.ai music request that balances screenshot context with user preferences, then refines based on cognitive load.\n\nPlease provide your response in this exact JSON format:\n{{\n  \"topic\": \"A detailed description of the music track (400-499 characters) that combines the screenshot work context with user preferences. Include key instruments, mood, tempo, and how it supports the user's current task.\",\n  \"tags\": \"Musical style/genre tags that balance the work activity with user preferences (max 100 characters)\",\n  \"negative_tags\": \"Styles or elements to avoid based on user preferences and work context (max 100 characters)\",\n  \"prompt\": null (REQUIRED multi-line lyrics when instrumental=false; no character limit. Leave null ONLY for instrumental tracks)\n}}\n\nBALANCE APPROACH:\n- Screenshot context + User preferences = PRIMARY (equal weight)\n- Cognitive load analysis = REFINEMENT (fine-tune the prompt)\n- Create music that feels both contextually appropriate AND personally satisfying\n\nThe prompt should be detailed and comprehensive, utilizing the full 500 character limit in topic to create the perfect musical environment.{}Return ONLY the JSON, no other text.",
-        preferences_context + &fe_context + &diversity_guidance
+        "CRITICAL: Analyze this screenshot and user preferences as EQUAL PRIMARY factors, then use cognitive load analysis to fine-tune the music generation.\n\nPRIMARY ANALYSIS (Equal Priority):\nSCREENSHOT CONTEXT:\n1. What application/website is the user actively using?\n2. What specific task are they performing right now?\n3. What is their current work state (focused, overwhelmed, creative, analytical)?\n4. What type of cognitive load are they experiencing?\n\nUSER PREFERENCES:\n5. What are the user's preferred genres, instruments, and artists?\n6. What energy level and mood do they prefer?\n7. What should be avoided based on their preferences?\n\nCOGNITIVE LOAD & CONTEXT REFINEMENT:\n8. Based on the cognitive load analysis, how should the music be adjusted?\n{}\nGenerate a complete Suno.ai music request that balances screenshot context with user preferences, then refines based on cognitive load.\n\nPlease provide your response in this exact JSON format:\n{{\n  \"topic\": \"A detailed description of the music track (400-499 characters) that combines the screenshot work context with user preferences. Include key instruments, mood, tempo, and how it supports the user's current task.\",\n  \"tags\": \"Musical style/genre tags that balance the work activity with user preferences (max 100 characters)\",\n  \"negative_tags\": \"Styles or elements to avoid based on user preferences and work context (max 100 characters)\",\n  \"prompt\": null (REQUIRED multi-line lyrics when instrumental=false; no character limit. Leave null ONLY for instrumental tracks),\n  \"cognitive_load\": \"high\", \"medium\" or \"low\" (your cognitive load assessment of the user's current task),\n  \"reason\": \"A few words on the visible evidence for your read of the screen (e.g. 'visible file tree and code editor')\"\n}}\n\nBALANCE APPROACH:\n- Screenshot context + User preferences = PRIMARY (equal weight)\n- Cognitive load analysis = REFINEMENT (fine-tune the prompt)\n- Create music that feels both contextually appropriate AND personally satisfying\n\nThe prompt should be detailed and comprehensive, utilizing the full 500 character limit in topic to create the perfect musical environment.{}Return ONLY the JSON, no other text.",
+        intent_lines(intents),
+        preferences_context + &negative_context + &fe_context + &language_rule(&lyrics_language(preferences, fe_prefs)) + &diversity_guidance
     )
 }
 
-pub(crate) async fn call_anthropic(client: &Client, api_key: &str, image_path: &Path, prompt: &str) -> Result<String> {
+// config.json instrumental_by_cognitive_load: the load assessment decides vocals, not the preference
+fn with_load_rule(prompt: String, enabled: bool) -> String {
+    if !enabled { return prompt; }
+    format!("{}\n\nINSTRUMENTAL BY COGNITIVE LOAD (overrides the instrumental preference above):\n- cognitive_load high: instrumental, prompt MUST be null (no distracting lyrics).\n- cognitive_load low: vocals are welcome, write lyrics in prompt.\n- cognitive_load medium: follow the user's instrumental preference.\nReturn ONLY the JSON, no other text.", prompt)
+}
+
+fn with_confidence_rule(prompt: String, min_confidence: f64) -> String {
+    if min_confidence <= 0.0 { return prompt; }
+    format!("{}\n\nCONFIDENCE: Also include \"confidence\": a number from 0 to 1 for how clearly the screenshot shows what the user is doing (0 = can't tell, 1 = unmistakable). Don't inflate it.\nReturn ONLY the JSON, no other text.", prompt)
+}
+
+// What the pipeline knows about the context beyond the screenshot
+#[derive(Debug, Clone)]
+pub enum ContextHint {
+    User(String), // a corrected tag or a classify_with_hint hint
+    Blend(String, String), // the two contexts the user keeps alternating between
+}
+
+// A user-asserted activity (correction or hint) overrides what Claude would infer from the screenshot.
+// Hint text is capped at `max_chars` so a long window title or hint can't blow the token budget.
+fn with_context_hint(prompt: String, hint: Option<&ContextHint>, max_chars: usize) -> String {
+    let cap = |text: &str| cap_context_text(text.trim(), max_chars);
+    match hint {
+        Some(ContextHint::User(tag)) => format!("{}\n\nUSER-ASSERTED GROUND TRUTH (highest priority, overrides what the screenshot seems to show): The user says their current activity is '{}'. Treat this as the screenshot context.\nReturn ONLY the JSON, no other text.", prompt, cap(tag)),
+        Some(ContextHint::Blend(a, b)) => format!("{}\n\nALTERNATING CONTEXTS: The user keeps switching back and forth between '{}' and '{}' (e.g. reading docs while coding). Make ONE track that suits both activities at once rather than either one, and mention both in the topic.\nReturn ONLY the JSON, no other text.", prompt, cap(a), cap(b)),
+        None => prompt,
+    }
+}
+
+const ANTHROPIC_DEFAULT_BASE: &str = "https://api.anthropic.com";
+
+// ANTHROPIC_BASE_URL overrides the host (local mocks, gateways)
+pub(crate) fn anthropic_base_url() -> String {
+    crate::config::base_url("ANTHROPIC_BASE_URL", ANTHROPIC_DEFAULT_BASE)
+}
+
+fn anthropic_messages_url() -> String {
+    format!("{}/v1/messages", anthropic_base_url())
+}
+
+// Read the screenshot and re-encode it per claude.json's image_format; png sends the file as-is
+fn encode_image_for_upload(image_path: &Path, upload: ImageUpload) -> Result<(String, &'static str)> {
     let image_bytes = fs::read(image_path).with_context(|| format!("Failed to read image: {}", image_path.display()))?;
-    let base64_data = BASE64_STD.encode(&image_bytes);
-    // determine media type
-    let media_type = match image_path.extension().and_then(|e| e.to_str()).map(|s| s.to_ascii_lowercase()) {
-        Some(ref ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
-        Some(ref ext) if ext == "png" => "image/png",
-        _ => "image/png",
+    let (bytes, media_type) = match upload.format {
+        ImageFormat::Png => {
+            let media_type = match image_path.extension().and_then(|e| e.to_str()).map(|s| s.to_ascii_lowercase()) {
+                Some(ref ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
+                _ => "image/png",
+            };
+            (image_bytes, media_type)
+        }
+        ImageFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb = image::load_from_memory(&image_bytes).context("Failed to decode screenshot")?.to_rgb8();
+            let mut out = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, upload.quality.clamp(1, 100))
+                .encode_image(&rgb)
+                .context("JPEG encode failed")?;
+            (out, "image/jpeg")
+        }
+        ImageFormat::Webp => {
+            let rgba = image::load_from_memory(&image_bytes).context("Failed to decode screenshot")?.to_rgba8();
+            let mut out = Vec::new();
+            image::codecs::webp::WebPEncoder::new_lossless(&mut out)
+                .encode(rgba.as_raw(), rgba.width(), rgba.height(), image::ColorType::Rgba8)
+                .context("WebP encode failed")?;
+            (out, "image/webp")
+        }
     };
+    if upload.format != ImageFormat::Png {
+        println!("Screenshot upload: {} bytes as {} (was {} bytes)", bytes.len(), media_type, image_bytes.len());
+    }
+    let (bytes, media_type) = fit_upload_limits(bytes, media_type)?;
+    Ok((BASE64_STD.encode(&bytes), media_type))
+}
+
+// Anthropic rejects images over 8000 px on a side or 5 MB (checked on the base64 form, which is what's sent)
+const MAX_IMAGE_SIDE: u32 = 8000;
+const MAX_IMAGE_BASE64_BYTES: usize = 5 * 1024 * 1024;
+// Below this the screenshot is no use for classification, so give up rather than shrink further
+const MIN_IMAGE_SIDE: u32 = 256;
+
+fn fits_upload_limits(len: usize, width: u32, height: u32) -> bool {
+    len.div_ceil(3) * 4 <= MAX_IMAGE_BASE64_BYTES && width <= MAX_IMAGE_SIDE && height <= MAX_IMAGE_SIDE
+}
+
+// Usually a no-op (only the header is read). An oversized image (ultrawide, huge PNG) is scaled under
+// the side limit and re-encoded as JPEG, stepping quality and then size down until it fits.
+fn fit_upload_limits(bytes: Vec<u8>, media_type: &'static str) -> Result<(Vec<u8>, &'static str)> {
+    let (width, height) = image::io::Reader::new(std::io::Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .context("Failed to read screenshot dimensions")?;
+    if fits_upload_limits(bytes.len(), width, height) { return Ok((bytes, media_type)); }
+
+    let mut img = image::load_from_memory(&bytes).context("Failed to decode screenshot")?;
+    if width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE {
+        img = img.resize(MAX_IMAGE_SIDE, MAX_IMAGE_SIDE, image::imageops::FilterType::Triangle);
+    }
+    loop {
+        let rgb = img.to_rgb8();
+        for quality in [85u8, 70, 55, 40] {
+            let mut out = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality)
+                .encode_image(&rgb)
+                .context("JPEG encode failed")?;
+            if fits_upload_limits(out.len(), rgb.width(), rgb.height()) {
+                println!(
+                    "Screenshot over Anthropic's limits ({}x{}, {} bytes): sending {}x{} JPEG at quality {} ({} bytes)",
+                    width, height, bytes.len(), rgb.width(), rgb.height(), quality, out.len()
+                );
+                return Ok((out, "image/jpeg"));
+            }
+        }
+        let (w, h) = (img.width() / 2, img.height() / 2);
+        if w.max(h) < MIN_IMAGE_SIDE {
+            anyhow::bail!(
+                "Screenshot ({}x{}, {} bytes) can't be made to fit Anthropic's image limits ({} px per side, {} MB)",
+                width, height, bytes.len(), MAX_IMAGE_SIDE, MAX_IMAGE_BASE64_BYTES / (1024 * 1024)
+            );
+        }
+        img = img.resize(w, h, image::imageops::FilterType::Triangle);
+    }
+}
+
+pub(crate) async fn call_anthropic(client: &Client, api_key: &str, image_path: &Path, prompt: &str, sampling: Sampling, upload: ImageUpload) -> Result<String> {
+    let (base64_data, media_type) = encode_image_for_upload(image_path, upload)?;
 
     let req = AnthropicRequest {
         model: "claude-3-5-haiku-latest".to_string(),
-        max_tokens: 2000,
+        max_tokens: sampling.max_tokens,
+        temperature: sampling.temperature,
         messages: vec![Message {
             role: "user".into(),
             content: vec![
@@ -166,36 +696,151 @@ pub(crate) async fn call_anthropic(client: &Client, api_key: &str, image_path: &
         }],
     };
 
+    post_messages(client, api_key, &req, "").await
+}
+
+// Openers of a refusal; only checked when the answer has no JSON object at all
+const REFUSAL_PHRASES: [&str; 8] = [
+    "i'm sorry", "i am sorry", "i apologize", "i can't", "i cannot", "i'm not able", "i am not able", "i'm unable",
+];
+
+// Claude declined to answer (stop_reason "refusal", or an apology where the JSON should be).
+// Kept as a distinct error so callers can report it and fall back instead of showing a parse failure.
+#[derive(Debug)]
+pub(crate) struct ClaudeRefusal {
+    pub message: String,
+}
+
+impl std::fmt::Display for ClaudeRefusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Claude declined the request: {}", self.message)
+    }
+}
+
+impl std::error::Error for ClaudeRefusal {}
+
+// Payload of the claude:refused event
+#[derive(Debug, Clone, Serialize)]
+pub struct ClaudeRefused {
+    pub message: String,
+    pub fallback_context: Option<crate::screenshot::ContextSummary>, // context kept instead (periodic pipeline only)
+}
+
+fn looks_like_refusal(text: &str) -> bool {
+    if text.contains('{') { return false; }
+    let lower = text.to_lowercase().replace('\u{2019}', "'");
+    REFUSAL_PHRASES.iter().any(|p| lower.contains(p))
+}
+
+// The refusal behind `err`, if that's what it was, through any added context
+pub(crate) fn refusal(err: &anyhow::Error) -> Option<&ClaudeRefusal> {
+    err.downcast_ref::<ClaudeRefusal>()
+}
+
+// Emit claude:refused when `err` is a refusal; returns whether it was
+pub(crate) fn report_refusal(app: &tauri::AppHandle, err: &anyhow::Error, fallback_context: Option<crate::screenshot::ContextSummary>) -> bool {
+    use tauri::Emitter;
+    let Some(refused) = refusal(err) else { return false };
+    println!("{}", refused);
+    let _ = app.emit("claude:refused", ClaudeRefused { message: refused.message.clone(), fallback_context });
+    true
+}
+
+// Claude's own confidence in its read of the screenshot was under config.json min_generation_confidence.
+// Returned before anything is saved, so no credits are spent and the current request stays in place.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LowConfidence {
+    pub confidence: f64,
+    pub min_confidence: f64,
+}
+
+impl std::fmt::Display for LowConfidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Claude's confidence in the screen context was {:.2}, below the minimum of {:.2}; skipped generation", self.confidence, self.min_confidence)
+    }
+}
+
+impl std::error::Error for LowConfidence {}
+
+// Payload of the context:low_confidence event
+#[derive(Debug, Clone, Serialize)]
+pub struct LowConfidenceSkipped {
+    pub confidence: f64,
+    pub min_confidence: f64,
+    pub fallback_context: Option<crate::screenshot::ContextSummary>, // context kept instead (periodic pipeline only)
+}
+
+pub(crate) fn low_confidence(err: &anyhow::Error) -> Option<&LowConfidence> {
+    err.downcast_ref::<LowConfidence>()
+}
+
+// Emit context:low_confidence when `err` is a low-confidence skip; returns whether it was
+pub(crate) fn report_low_confidence(app: &tauri::AppHandle, err: &anyhow::Error, fallback_context: Option<crate::screenshot::ContextSummary>) -> bool {
+    use tauri::Emitter;
+    let Some(low) = low_confidence(err) else { return false };
+    println!("{}", low);
+    let _ = app.emit("context:low_confidence", LowConfidenceSkipped { confidence: low.confidence, min_confidence: low.min_confidence, fallback_context });
+    true
+}
+
+// Rough tokens for a screenshot when the API doesn't report usage (images are ~1.6k tokens at our sizes)
+const IMAGE_TOKENS_ESTIMATE: u64 = 1600;
+
+// Add this call to the spend estimate: the API's token usage when present, else ~4 chars per token
+fn record_spend(req: &AnthropicRequest, usage: Option<&Usage>, output: Option<&str>) {
+    let (input_tokens, output_tokens) = match usage {
+        Some(u) => (u.input_tokens, u.output_tokens),
+        None => {
+            let input: u64 = req.messages.iter().flat_map(|m| &m.content).map(|c| match &c.text {
+                Some(t) => t.chars().count() as u64 / 4,
+                None => IMAGE_TOKENS_ESTIMATE,
+            }).sum();
+            (input, output.map_or(0, |o| o.chars().count() as u64 / 4))
+        }
+    };
+    crate::spend::record_claude(&req.model, input_tokens, output_tokens);
+}
+
+// POST to the Messages API and return the joined text; `label` tags errors from the different callers
+async fn post_messages(client: &Client, api_key: &str, req: &AnthropicRequest, label: &str) -> Result<String> {
     let res = client
-        .post("https://api.anthropic.com/v1/messages")
+        .post(anthropic_messages_url())
         .header("x-api-key", api_key)
         .header("anthropic-version", "2023-06-01")
         .header("content-type", "application/json")
-        .json(&req)
+        .json(req)
         .send()
         .await
-        .context("Failed to call Anthropic API")?;
+        .with_context(|| format!("Failed to call Anthropic API{}", label))?;
     let status = res.status();
     let text = res.text().await.unwrap_or_default();
+    if matches!(status, reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) {
+        anyhow::bail!("Anthropic API key rejected ({}): check ANTHROPIC_API_KEY (set_api_key or .env) is valid and not expired", status);
+    }
     if !status.is_success() { anyhow::bail!("Anthropic error ({}): {}", status, text); }
-    let parsed: AnthropicResponse = serde_json::from_str(&text).context("Parse Anthropic response failed")?;
-    let first = parsed.content.first().ok_or_else(|| anyhow::anyhow!("Empty content from Anthropic"))?;
-    Ok(first.text.clone())
+    let parsed: AnthropicResponse = serde_json::from_str(&text).with_context(|| format!("Parse Anthropic response failed{}", label))?;
+    let joined = parsed.joined_text();
+    record_spend(req, parsed.usage.as_ref(), joined.as_deref());
+    answer_text(parsed.stop_reason.as_deref(), joined, label)
+}
+
+// The text of a successful answer; a ClaudeRefusal when it was declined, for report_refusal
+fn answer_text(stop_reason: Option<&str>, joined: Option<String>, label: &str) -> Result<String> {
+    if stop_reason == Some("refusal") || joined.as_deref().is_some_and(looks_like_refusal) {
+        let message = joined.unwrap_or_else(|| "stop_reason: refusal".to_string());
+        return Err(ClaudeRefusal { message: message.trim().to_string() }.into());
+    }
+    joined.ok_or_else(|| anyhow::anyhow!("Empty content from Anthropic{}", label))
 }
 
 // Faster, lightweight variant for quick classification
-pub(crate) async fn call_anthropic_quick(client: &Client, api_key: &str, image_path: &Path, prompt: &str) -> Result<String> {
-    let image_bytes = fs::read(image_path).with_context(|| format!("Failed to read image: {}", image_path.display()))?;
-    let base64_data = BASE64_STD.encode(&image_bytes);
-    let media_type = match image_path.extension().and_then(|e| e.to_str()).map(|s| s.to_ascii_lowercase()) {
-        Some(ref ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
-        Some(ref ext) if ext == "png" => "image/png",
-        _ => "image/png",
-    };
+pub(crate) async fn call_anthropic_quick(client: &Client, api_key: &str, image_path: &Path, prompt: &str, sampling: Sampling, upload: ImageUpload) -> Result<String> {
+    let (base64_data, media_type) = encode_image_for_upload(image_path, upload)?;
 
     let req = AnthropicRequest {
         model: "claude-3-haiku-20240307".to_string(),
-        max_tokens: 300,
+        max_tokens: sampling.max_tokens,
+        temperature: sampling.temperature,
         messages: vec![Message {
             role: "user".into(),
             content: vec![
@@ -205,29 +850,29 @@ pub(crate) async fn call_anthropic_quick(client: &Client, api_key: &str, image_p
         }],
     };
 
-    let res = client
-        .post("https://api.anthropic.com/v1/messages")
-        .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("content-type", "application/json")
-        .json(&req)
-        .send()
-        .await
-        .context("Failed to call Anthropic API (quick)")?;
-    let status = res.status();
-    let text = res.text().await.unwrap_or_default();
-    if !status.is_success() { anyhow::bail!("Anthropic error ({}): {}", status, text); }
-    let parsed: AnthropicResponse = serde_json::from_str(&text).context("Parse Anthropic response failed (quick)")?;
-    let first = parsed.content.first().ok_or_else(|| anyhow::anyhow!("Empty content from Anthropic (quick)"))?;
-    Ok(first.text.clone())
+    post_messages(client, api_key, &req, " (quick)").await
+}
+
+// Text-only call on the quick model, for follow-ups that don't need the screenshot again
+async fn call_anthropic_text(client: &Client, api_key: &str, prompt: &str, sampling: Sampling) -> Result<String> {
+    let req = AnthropicRequest {
+        model: "claude-3-haiku-20240307".to_string(),
+        max_tokens: sampling.max_tokens,
+        temperature: sampling.temperature,
+        messages: vec![Message {
+            role: "user".into(),
+            content: vec![Content { content_type: "text".into(), text: Some(prompt.to_string()), source: None }],
+        }],
+    };
+    post_messages(client, api_key, &req, " (text)").await
 }
 
 pub(crate) fn extract_json_block(s: &str) -> Option<String> {
     // If Claude returned a fenced block ```json ... ```, strip the fences first
     let trimmed = s.trim();
     let without_fence = if let Some(start) = trimmed.find("```") {
-        // try to find the closing fence
-        if let Some(end) = trimmed.rfind("```") {
+        // try to find the closing fence; a reply cut off after the opening one has none
+        if let Some(end) = trimmed.rfind("```").filter(|&end| end > start) {
             let inner = &trimmed[start + 3..end];
             // remove optional 'json' language hint
             inner.trim_start_matches(|c: char| c == 'j' || c == 's' || c == 'o' || c == 'n' || c.is_whitespace()).trim()
@@ -244,6 +889,22 @@ pub(crate) fn extract_json_block(s: &str) -> Option<String> {
     Some(without_fence[start..=end].to_string())
 }
 
+// Strict JSON first so valid output is never reinterpreted; only near-misses (trailing commas,
+// unquoted or single-quoted keys) fall back to JSON5, logged so we can see how often it happens
+pub(crate) fn parse_json_lenient(s: &str, label: &str) -> Result<Value> {
+    let strict_err = match serde_json::from_str(s) {
+        Ok(v) => return Ok(v),
+        Err(e) => e,
+    };
+    match json5::from_str::<Value>(s) {
+        Ok(v) => {
+            println!("Parsed {} with the lenient JSON parser (strict parse failed: {})", label, strict_err);
+            Ok(v)
+        }
+        Err(_) => Err(strict_err).with_context(|| format!("Failed to parse {}", label)),
+    }
+}
+
 fn as_string(value: Option<&Value>) -> Option<String> {
     match value {
         Some(Value::String(s)) => Some(s.clone()),
@@ -258,21 +919,92 @@ fn as_string(value: Option<&Value>) -> Option<String> {
     }
 }
 
+// Fit `s` into `max` chars (not bytes) including the "..." suffix, cutting at the last word boundary;
+// a single word longer than the limit is cut mid-word
 fn shorten(s: &str, max: usize) -> String {
-    if s.len() <= max { return s.to_string(); }
-    let take = max.saturating_sub(3);
-    format!("{}...", s.chars().take(take).collect::<String>())
+    if s.chars().count() <= max { return s.to_string(); }
+    if max < 3 { return s.chars().take(max).collect(); }
+    let budget = max - 3;
+    let head: String = s.chars().take(budget).collect();
+    let ends_on_boundary = s.chars().nth(budget).is_some_and(char::is_whitespace);
+    let cut = match head.rfind(char::is_whitespace) {
+        _ if ends_on_boundary => head.as_str(),
+        Some(i) if i > 0 => &head[..i],
+        _ => head.as_str(),
+    };
+    format!("{}...", cut.trim_end().trim_end_matches([',', ';']))
+}
+
+fn push_unique_tag(tags: &mut Vec<String>, tag: &str) {
+    if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) { tags.push(tag.to_string()); }
+}
+
+// The user's permanent vetoes first, then Claude's, without duplicates; whole tags that don't fit
+// in `max_len` are dropped
+fn merge_negative_tags(global: &[String], from_claude: Option<&str>, max_len: usize) -> Option<String> {
+    let mut merged: Vec<String> = Vec::new();
+    let claude = from_claude.unwrap_or_default().split(',').map(str::trim).filter(|t| !t.is_empty());
+    for tag in global.iter().map(String::as_str).chain(claude) {
+        push_unique_tag(&mut merged, tag);
+    }
+    let mut kept: Vec<String> = Vec::new();
+    for tag in merged {
+        let len = kept.iter().map(|t| t.chars().count() + 2).sum::<usize>() + tag.chars().count();
+        if len <= max_len { kept.push(tag); }
+    }
+    if kept.is_empty() { None } else { Some(kept.join(", ")) }
+}
+
+// Interleave up to `max_frontend` frontend genres with the context tags (frontend first), then fit to
+// `max_len` keeping whole tags and always at least one context tag when there is one
+fn merge_tags(frontend: &[String], context: &str, max_frontend: usize, max_len: usize) -> String {
+    let fe: Vec<&str> = frontend.iter().map(|g| g.trim()).filter(|g| !g.is_empty()).take(max_frontend).collect();
+    let ctx: Vec<&str> = context.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()).collect();
+    let mut merged: Vec<String> = Vec::new();
+    for i in 0..fe.len().max(ctx.len()) {
+        if let Some(f) = fe.get(i) { push_unique_tag(&mut merged, f); }
+        if let Some(c) = ctx.get(i) { push_unique_tag(&mut merged, c); }
+    }
+    let is_context = |t: &str| ctx.iter().any(|c| c.eq_ignore_ascii_case(t));
+
+    let joined_len = |v: &[String]| v.iter().map(|t| t.chars().count()).sum::<usize>() + v.len().saturating_sub(1) * 2;
+    let mut kept: Vec<String> = Vec::new();
+    for t in &merged {
+        kept.push(t.clone());
+        if joined_len(&kept) > max_len { kept.pop(); }
+    }
+    if !ctx.is_empty() && !kept.iter().any(|t| is_context(t)) {
+        // Make room for the first context tag by dropping from the end
+        if let Some(first_ctx) = merged.iter().find(|t| is_context(t)).cloned() {
+            kept.push(first_ctx);
+            while kept.len() > 1 && joined_len(&kept) > max_len { kept.remove(kept.len() - 2); }
+        }
+    }
+    shorten(&kept.join(", "), max_len)
 }
 
-fn build_hackmit_req_from_claude(json_str: &str, prefs: &Option<UserPreferences>) -> Result<HackmitGenerateReq> {
-    // Try strict parse first
-    let mut v: Value = serde_json::from_str(json_str).context("Failed to parse Claude JSON")?;
+fn build_hackmit_req_from_claude(json_str: &str, prefs: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>, load_adaptive: bool, min_confidence: f64) -> Result<HackmitGenerateReq> {
+    // Strict parse first, JSON5 for near-misses
+    let mut v = parse_json_lenient(json_str, "Claude JSON")?;
 
     // Support top-level object or nested under a known key
     if let Some(obj) = v.get("request").cloned() { v = obj; }
 
+    if min_confidence > 0.0 {
+        // Missing or unparsable confidence isn't a reason to skip; only a low stated one is
+        let confidence = v.get("confidence").and_then(|c| c.as_f64().or_else(|| c.as_str()?.trim().parse().ok()));
+        match confidence {
+            Some(c) if c.clamp(0.0, 1.0) < min_confidence => {
+                return Err(LowConfidence { confidence: c.clamp(0.0, 1.0), min_confidence }.into());
+            }
+            Some(c) => println!("Context confidence {:.2} (minimum {:.2})", c, min_confidence),
+            None => println!("Claude gave no confidence, generating anyway"),
+        }
+    }
+
     let topic = as_string(v.get("topic")).or_else(|| as_string(v.get("title")));
     let tags = as_string(v.get("tags"));
+    let negative_tags = merge_negative_tags(&global_negative_tags(prefs), as_string(v.get("negative_tags")).as_deref(), MAX_TAGS_CHARS);
     let prompt = as_string(v.get("prompt"));
 
     let topic = topic.unwrap_or_else(|| "Generated track".to_string());
@@ -280,17 +1012,220 @@ fn build_hackmit_req_from_claude(json_str: &str, prefs: &Option<UserPreferences>
     tags = shorten(&tags, 100);
     let prompt = prompt; // do NOT shorten lyrics; no character limit
 
-    let make_instrumental = prefs.as_ref().and_then(|p| p.make_instrumental).unwrap_or(true);
+    let mut make_instrumental = resolve_instrumental(prefs, fe_prefs);
+    if load_adaptive {
+        // High load forces instrumental; low load allows vocals, but only if Claude actually wrote lyrics
+        let load = as_string(v.get("cognitive_load")).map(|l| l.trim().to_lowercase());
+        match load.as_deref() {
+            Some("high") => make_instrumental = true,
+            Some("low") if prompt.is_some() => make_instrumental = false,
+            _ => {}
+        }
+        println!("Cognitive load {:?}: instrumental = {}", load, make_instrumental);
+    }
     Ok(HackmitGenerateReq {
         topic: Some(topic),
         tags: Some(tags),
+        negative_tags,
         prompt,
         make_instrumental: Some(make_instrumental),
         cover_clip_id: None,
+        reason: as_string(v.get("reason")).map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
     })
 }
 
-pub async fn regenerate_suno_request_json() -> Result<HackmitGenerateReq> {
+// Jaccard overlap of lowercase word tokens; 1.0 means the same vocabulary
+fn topic_similarity(a: &str, b: &str) -> f32 {
+    use std::collections::HashSet;
+    let tokens = |s: &str| -> HashSet<String> {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|t| t.len() > 2)
+            .map(|t| t.to_lowercase())
+            .collect()
+    };
+    let (ta, tb) = (tokens(a), tokens(b));
+    let union = ta.union(&tb).count();
+    if union == 0 { return 0.0; }
+    ta.intersection(&tb).count() as f32 / union as f32
+}
+
+const TOPIC_SIMILARITY_THRESHOLD: f32 = 0.6;
+
+// Similarity of `topic` to the previous track's when it's close enough to retry; None when either is missing
+fn topic_too_similar(prev: Option<&str>, topic: Option<&str>) -> Option<f32> {
+    let similarity = topic_similarity(prev?, topic?);
+    (similarity >= TOPIC_SIMILARITY_THRESHOLD).then_some(similarity)
+}
+
+// Topic length the generation prompt asks for
+const TOPIC_CHARS: std::ops::RangeInclusive<usize> = 400..=499;
+
+// How many chars `len` is outside TOPIC_CHARS
+fn topic_length_miss(len: usize) -> usize {
+    if len < *TOPIC_CHARS.start() { TOPIC_CHARS.start() - len } else { len.saturating_sub(*TOPIC_CHARS.end()) }
+}
+
+fn topic_rewrite_prompt(topic: &str, len: usize) -> String {
+    format!(
+        "Below is a music track description that was supposed to be {}-{} characters long, but it is {} characters.\nRewrite it to be {}-{} characters, keeping its instruments, mood, tempo and purpose. {} Do not pad it with generic filler.\nReturn ONLY JSON as {{\"topic\": \"...\"}}, no other text.\n\n{}",
+        TOPIC_CHARS.start(),
+        TOPIC_CHARS.end(),
+        len,
+        TOPIC_CHARS.start(),
+        TOPIC_CHARS.end(),
+        if len < *TOPIC_CHARS.start() { "Add concrete musical detail." } else { "Tighten it." },
+        topic
+    )
+}
+
+// Last request we wrote to suno-config/suno_request.json
+fn load_last_request(root: &Path) -> Option<HackmitGenerateReq> {
+    let txt = fs::read_to_string(root.join("suno-config").join("suno_request.json")).ok()?;
+    serde_json::from_str(&txt).ok()
+}
+
+// Write the canonical suno-config/suno_request.json and add it to the session timeline
+pub(crate) fn save_request(root: &Path, req: &HackmitGenerateReq) -> Result<()> {
+    let dir = root.join("suno-config");
+    let _ = fs::create_dir_all(&dir);
+    let pretty = serde_json::to_string_pretty(req)?;
+    fs::write(dir.join("suno_request.json"), &pretty).context("Failed to write suno_request.json")?;
+    crate::session::record(root, "request", req);
+    Ok(())
+}
+
+// Topic of the last request we wrote, used to avoid near-identical consecutive tracks
+fn load_previous_topic(root: &Path) -> Option<String> {
+    load_last_request(root)?.topic
+}
+
+// Everything needed to send the screenshot + a prompt to Claude
+struct ClaudeCall<'a> {
+    client: &'a Client,
+    api_key: &'a str,
+    shot: &'a Path,
+    sampling: Sampling,
+    upload: ImageUpload,
+    root: &'a Path,
+    debug_capture: bool, // keep raw responses under suno-config/debug (config.json debug_capture)
+    retry_topic_length: bool, // claude.json retry_topic_length
+    load_adaptive: bool, // config.json instrumental_by_cognitive_load
+    min_confidence: f64, // config.json min_generation_confidence
+    timeout: Duration, // claude.json classify_timeout_secs, for the screenshot call
+}
+
+// Write a raw Claude response to suno-config/debug/<unix_ms><suffix>.txt and note it in the session timeline
+fn save_debug_response(root: &Path, raw: &str, suffix: &str) {
+    let dir = root.join("suno-config").join("debug");
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join(format!("{}{}.txt", now_ms(), suffix));
+    match fs::write(&path, raw) {
+        Ok(()) => {
+            println!("Saved raw Claude response to {}", path.display());
+            crate::session::record(root, "claude_raw", &serde_json::json!({ "path": path }));
+        }
+        Err(e) => println!("Failed to save raw Claude response: {}", e),
+    }
+}
+
+fn parse_claude_request(raw: &str, prefs: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>, load_adaptive: bool, min_confidence: f64) -> Result<HackmitGenerateReq> {
+    let json_block = match extract_json_block(raw) {
+        Some(s) => s,
+        None => {
+            // Try raw as-is in case Claude responded with bare JSON
+            if serde_json::from_str::<Value>(raw).is_ok() { raw.to_string() } else {
+                anyhow::bail!("Claude response did not contain JSON block or parsable JSON")
+            }
+        }
+    };
+    build_hackmit_req_from_claude(&json_block, prefs, fe_prefs, load_adaptive, min_confidence)
+}
+
+fn repair_prompt(raw: &str) -> String {
+    format!(
+        "The text below was supposed to be a single JSON object with the keys \"topic\" (string), \"tags\" (string), \"negative_tags\" (string), \"prompt\" (string or null) and \"cognitive_load\" (\"high\", \"medium\" or \"low\") and optionally \"reason\" (string) and \"confidence\" (number), but it is not valid JSON.\nFix it into valid JSON matching that schema without changing the content.\nReturn ONLY the JSON, no other text.\n\n{}",
+        raw
+    )
+}
+
+async fn ask_claude_for_request(call: &ClaudeCall<'_>, prompt: &str, prefs: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>) -> Result<HackmitGenerateReq> {
+    let raw = tokio::time::timeout(call.timeout, call_anthropic(call.client, call.api_key, call.shot, prompt, call.sampling, call.upload))
+        .await
+        .map_err(|_| anyhow::anyhow!("Claude call timed out after {}s", call.timeout.as_secs()))??;
+    if call.debug_capture { save_debug_response(call.root, &raw, ""); }
+    match parse_claude_request(&raw, prefs, fe_prefs, call.load_adaptive, call.min_confidence) {
+        Ok(req) => Ok(req),
+        Err(e) if low_confidence(&e).is_some() => Err(e),
+        Err(e) => {
+            // One repair round only; a second failure is returned as-is
+            println!("Claude JSON unusable ({:#}), asking for one repair", e);
+            let sampling = Sampling { max_tokens: call.sampling.max_tokens, temperature: Some(0.0) };
+            let repaired = call_anthropic_text(call.client, call.api_key, &repair_prompt(&raw), sampling).await?;
+            if call.debug_capture { save_debug_response(call.root, &repaired, "-repair"); }
+            let req = parse_claude_request(&repaired, prefs, fe_prefs, call.load_adaptive, call.min_confidence).context("Claude JSON still invalid after repair")?;
+            println!("Claude JSON repaired");
+            Ok(req)
+        }
+    }
+}
+
+// Ask Claude for a request; if the topic is too close to the previous one, retry once with an explicit nudge
+async fn request_from_claude(call: &ClaudeCall<'_>, prompt: &str, prefs: &Option<UserPreferences>, fe_prefs: &Option<FrontendPreferences>, prev_topic: Option<&str>) -> Result<HackmitGenerateReq> {
+    let req = ask_claude_for_request(call, prompt, prefs, fe_prefs).await?;
+    let Some(similarity) = topic_too_similar(prev_topic, req.topic.as_deref()) else { return Ok(fit_topic_length(call, req).await); };
+    let prev = prev_topic.unwrap_or_default();
+    println!("Topic too similar to previous track ({:.2}), regenerating once", similarity);
+    let nudged = format!("{}\n\nIMPORTANT: The previous track was too similar. Produce something distinct from: {}\nReturn ONLY the JSON, no other text.", prompt, prev);
+    let req = ask_claude_for_request(call, &nudged, prefs, fe_prefs).await?;
+    Ok(fit_topic_length(call, req).await)
+}
+
+// With retry_topic_length, ask once (text only) for a rewrite of a topic outside TOPIC_CHARS. The rewrite
+// is kept only if it's closer to the range; a failed or worse rewrite leaves Claude's original topic.
+async fn fit_topic_length(call: &ClaudeCall<'_>, mut req: HackmitGenerateReq) -> HackmitGenerateReq {
+    if !call.retry_topic_length { return req; }
+    let Some(topic) = req.topic.clone() else { return req };
+    let len = topic.chars().count();
+    if TOPIC_CHARS.contains(&len) { return req; }
+    println!("Topic is {} chars (want {}-{}), asking for one rewrite", len, TOPIC_CHARS.start(), TOPIC_CHARS.end());
+    let rewritten = match call_anthropic_text(call.client, call.api_key, &topic_rewrite_prompt(&topic, len), call.sampling).await {
+        Ok(raw) => {
+            if call.debug_capture { save_debug_response(call.root, &raw, "-topic"); }
+            extract_json_block(&raw)
+                .and_then(|json| parse_json_lenient(&json, "topic rewrite JSON").ok())
+                .and_then(|v| as_string(v.get("topic")))
+        }
+        Err(e) => {
+            println!("Topic rewrite failed: {:#}", e);
+            None
+        }
+    };
+    match rewritten {
+        Some(new) if topic_length_miss(new.chars().count()) < topic_length_miss(len) => {
+            println!("Topic rewritten to {} chars", new.chars().count());
+            req.topic = Some(new);
+        }
+        _ => println!("Topic rewrite didn't get closer to {}-{} chars, keeping the original", TOPIC_CHARS.start(), TOPIC_CHARS.end()),
+    }
+    req
+}
+
+// A generated request together with what it was generated from, for logs and "why this track?"
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratedRequest {
+    pub request: HackmitGenerateReq,
+    pub screenshot: PathBuf, // the image Claude was sent
+    pub context: Option<crate::screenshot::ContextSummary>, // set by the periodic pipeline, which classified the frame first
+    pub recent_genres: Vec<String>, // most recent first, as they were before this request was remembered
+}
+
+impl GeneratedRequest {
+    fn new(request: HackmitGenerateReq, screenshot: PathBuf, recent: &[RecentGenre]) -> Self {
+        Self { request, screenshot, context: None, recent_genres: recent.iter().map(|g| g.genre.clone()).collect() }
+    }
+}
+
+pub async fn regenerate_suno_request_json(client: &Client, hint: Option<&ContextHint>) -> Result<GeneratedRequest> {
     // Load env (.env at project root)
     let _ = dotenvy::dotenv();
     // Find root and latest screenshot
@@ -302,47 +1237,28 @@ pub async fn regenerate_suno_request_json() -> Result<HackmitGenerateReq> {
     let shot = find_latest_screenshot(&temp_dir)?;
     let prefs = load_user_preferences(&root);
     let recent = load_recent_genres(&root);
-    let prompt = build_prompt(&prefs, &recent, &None);
+    let pin = load_active_pin(&root);
+    let app_config = crate::config::load_config(&root);
+    let claude_config = load_claude_config(&root)?;
+    let prompt = with_context_hint(with_confidence_rule(with_load_rule(build_prompt(&prefs, mentioned_genres(&root, &recent), &None, pin.as_ref(), session_seed(&root), &load_intent_map(&root)), app_config.instrumental_by_cognitive_load), app_config.min_generation_confidence), hint, claude_config.classify_context_chars);
 
-    let api_key = std::env::var("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set in .env")?;
-    let client = Client::new();
-    let raw = call_anthropic(&client, &api_key, &shot, &prompt).await?;
-    let json_block = match extract_json_block(&raw) {
-        Some(s) => s,
-        None => {
-            // Try raw as-is in case Claude responded with bare JSON
-            if serde_json::from_str::<Value>(&raw).is_ok() { raw.clone() } else {
-                anyhow::bail!("Claude response did not contain JSON block or parsable JSON")
-            }
-        }
-    };
-    let req = build_hackmit_req_from_claude(&json_block, &prefs)?;
+    let api_key = crate::keys::api_key("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set in the keychain or .env")?;
+    let sampling = claude_config.generation(resolve_instrumental(&prefs, &None));
+    let call = ClaudeCall { client, api_key: &api_key, shot: &shot, sampling, upload: claude_config.upload(), root: &root, debug_capture: app_config.debug_capture, retry_topic_length: claude_config.retry_topic_length, load_adaptive: app_config.instrumental_by_cognitive_load, min_confidence: app_config.min_generation_confidence, timeout: Duration::from_secs(claude_config.classify_timeout_secs) };
+    let prev_topic = load_previous_topic(&root);
+    let req = request_from_claude(&call, &prompt, &prefs, &None, prev_topic.as_deref()).await?;
 
     // Update recent genres with the new tags (keep most recent first, unique, max 5)
-    if let Some(tags) = req.tags.clone() {
-        let mut current = load_recent_genres(&root);
-        let mut new_list = extract_primary_genres(&tags);
-        // Prepend new genres in order, ensuring uniqueness and recency
-        for g in new_list.drain(..) {
-            let gnorm = g.to_lowercase();
-            current.retain(|x| x.to_lowercase() != gnorm);
-            current.insert(0, g);
-        }
-        // cap to 5
-        if current.len() > 5 { current.truncate(5); }
-        let _ = save_recent_genres(&root, &current);
+    if let Some(tags) = req.tags.as_deref() {
+        remember_genres(&root, tags, req.make_instrumental);
     }
 
     // Save only to suno-config/suno_request.json (canonical)
-    let dir = root.join("suno-config");
-    let _ = fs::create_dir_all(&dir);
-    let underscore = dir.join("suno_request.json");
-    let pretty = serde_json::to_string_pretty(&req)?;
-    fs::write(&underscore, &pretty).context("Failed to write suno_request.json")?;
-    Ok(req)
+    save_request(&root, &req)?;
+    Ok(GeneratedRequest::new(req, shot, &recent))
 }
 
-pub async fn regenerate_suno_request_json_with_prefs(fe_prefs: FrontendPreferences) -> Result<HackmitGenerateReq> {
+pub async fn regenerate_suno_request_json_with_prefs(client: &Client, fe_prefs: FrontendPreferences, hint: Option<&ContextHint>) -> Result<GeneratedRequest> {
     // Load env (.env at project root)
     let _ = dotenvy::dotenv();
     let root = project_root()?;
@@ -352,80 +1268,225 @@ pub async fn regenerate_suno_request_json_with_prefs(fe_prefs: FrontendPreferenc
     let shot = find_latest_screenshot(&temp_dir)?;
     let prefs = load_user_preferences(&root);
     let recent = load_recent_genres(&root);
-    let prompt = build_prompt(&prefs, &recent, &Some(fe_prefs.clone()));
+    let pin = load_active_pin(&root);
+    let app_config = crate::config::load_config(&root);
+    let claude_config = load_claude_config(&root)?;
+    let prompt = with_context_hint(with_confidence_rule(with_load_rule(build_prompt(&prefs, mentioned_genres(&root, &recent), &Some(fe_prefs.clone()), pin.as_ref(), session_seed(&root), &load_intent_map(&root)), app_config.instrumental_by_cognitive_load), app_config.min_generation_confidence), hint, claude_config.classify_context_chars);
 
-    let api_key = std::env::var("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set in .env")?;
-    let client = Client::new();
-    let raw = call_anthropic(&client, &api_key, &shot, &prompt).await?;
-    let json_block = match extract_json_block(&raw) {
-        Some(s) => s,
-        None => {
-            if serde_json::from_str::<Value>(&raw).is_ok() { raw.clone() } else {
-                anyhow::bail!("Claude response did not contain JSON block or parsable JSON")
-            }
-        }
-    };
-    let mut req = build_hackmit_req_from_claude(&json_block, &prefs)?;
+    let api_key = crate::keys::api_key("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set in the keychain or .env")?;
+    let fe = Some(fe_prefs.clone());
+    let sampling = claude_config.generation(resolve_instrumental(&prefs, &fe));
+    let call = ClaudeCall { client, api_key: &api_key, shot: &shot, sampling, upload: claude_config.upload(), root: &root, debug_capture: app_config.debug_capture, retry_topic_length: claude_config.retry_topic_length, load_adaptive: app_config.instrumental_by_cognitive_load, min_confidence: app_config.min_generation_confidence, timeout: Duration::from_secs(claude_config.classify_timeout_secs) };
+    let prev_topic = load_previous_topic(&root);
+    let mut req = request_from_claude(&call, &prompt, &prefs, &fe, prev_topic.as_deref()).await?;
 
-    // Apply frontend preferences: instrumental/lyrics and vocals gender
-    if let Some(instr) = fe_prefs.instrumental { req.make_instrumental = Some(instr); }
+    // Apply frontend preferences: genres and lyrics (instrumental is resolved in build_hackmit_req_from_claude)
     if let Some(genres) = fe_prefs.genres.clone() {
-        // Prepend frontend genres to tags if not already present
-        let mut tags = req.tags.clone().unwrap_or_default();
+        // Interleave a capped number of frontend genres with Claude's context tags
         if !genres.is_empty() {
-            let g = genres.join(", ");
-            if tags.is_empty() { tags = g; } else { tags = format!("{}, {}", g, tags); }
-            req.tags = Some(shorten(&tags, 100));
+            let max_frontend = crate::config::load_config(&root).max_frontend_genres;
+            let tags = req.tags.clone().unwrap_or_default();
+            req.tags = Some(merge_tags(&genres, &tags, max_frontend, 100));
         }
     }
 
     // Ensure lyrics present if vocals requested but prompt is empty
     if matches!(req.make_instrumental, Some(false)) && req.prompt.is_none() {
-        let fallback = if fe_prefs.silly_mode.unwrap_or(false) {
-            "Verse 1:\nOn my screen the windows dance, tabs and tasks collide\nShortcut sparks and midnight marks, pixels as my guide\nChorus:\nClick clack, bring the groove back, let the workflow sing\nLaughing through the chaos while I do my thing\n"
-        } else {
-            "Verse 1:\nDrafting dreams in quiet rooms, chasing melody\nFinding light in steady lines, calm complexity\nChorus:\nPull me closer, hold the moment, let the night begin\nIn the hush between these pages, I can breathe again\n"
-        };
-        req.prompt = Some(fallback.to_string()); // no truncation
+        let language = lyrics_language(&prefs, &fe);
+        req.prompt = Some(fallback_lyrics(&root, &language, fe_prefs.silly_mode.unwrap_or(false))); // no truncation
     }
 
     // Update recent genres tracking
-    if let Some(tags) = req.tags.clone() {
-        let mut current = load_recent_genres(&root);
-        let mut new_list = extract_primary_genres(&tags);
-        for g in new_list.drain(..) {
-            let gnorm = g.to_lowercase();
-            current.retain(|x| x.to_lowercase() != gnorm);
-            current.insert(0, g);
-        }
-        if current.len() > 5 { current.truncate(5); }
-        let _ = save_recent_genres(&root, &current);
+    if let Some(tags) = req.tags.as_deref() {
+        remember_genres(&root, tags, req.make_instrumental);
     }
 
     // Persist and return
-    let dir = root.join("suno-config");
-    let _ = std::fs::create_dir_all(&dir);
-    let underscore = dir.join("suno_request.json");
-    let pretty = serde_json::to_string_pretty(&req)?;
-    std::fs::write(&underscore, &pretty).context("Failed to write suno_request.json")?;
+    save_request(&root, &req)?;
+    Ok(GeneratedRequest::new(req, shot, &recent))
+}
+
+// Style for a local request when no earlier request exists to borrow one from
+const LOCAL_DEFAULT_TAGS: &str = "ambient, lofi, instrumental";
+
+// Request built without Claude for a locally classified context (config.json app_tags / window title):
+// the last request's style, or a neutral one, with a topic naming the activity. Preferences apply as on
+// the Claude path; vocal tracks get the stand-in lyrics.
+pub(crate) fn local_request(context: &crate::screenshot::ContextSummary, fe_prefs: Option<FrontendPreferences>) -> Result<GeneratedRequest> {
+    let root = project_root()?;
+    let shot = find_latest_screenshot(&root.join("temp")).unwrap_or_default();
+    let prefs = load_user_preferences(&root);
+    let recent = load_recent_genres(&root);
+    let last = load_last_request(&root).unwrap_or_default();
+    let mut tags = last.tags.filter(|t| !t.trim().is_empty()).unwrap_or_else(|| LOCAL_DEFAULT_TAGS.to_string());
+    if let Some(genres) = fe_prefs.as_ref().and_then(|f| f.genres.clone()).filter(|g| !g.is_empty()) {
+        tags = merge_tags(&genres, &tags, crate::config::load_config(&root).max_frontend_genres, MAX_TAGS_CHARS);
+    }
+    let instrumental = resolve_instrumental(&prefs, &fe_prefs);
+    let silly = fe_prefs.as_ref().and_then(|f| f.silly_mode).unwrap_or(false);
+    let req = HackmitGenerateReq {
+        topic: Some(format!("Background music for {}: {}", context.tag, context.details)),
+        tags: Some(tags),
+        negative_tags: merge_negative_tags(&global_negative_tags(&prefs), last.negative_tags.as_deref(), MAX_TAGS_CHARS),
+        prompt: (!instrumental).then(|| fallback_lyrics(&root, &lyrics_language(&prefs, &fe_prefs), silly)),
+        make_instrumental: Some(instrumental),
+        cover_clip_id: None,
+        reason: context.reason.clone(),
+    };
+    save_request(&root, &req)?;
+    Ok(GeneratedRequest::new(req, shot, &recent))
+}
+
+const FALLBACK_LYRICS_SERIOUS: &str = "Verse 1:\nDrafting dreams in quiet rooms, chasing melody\nFinding light in steady lines, calm complexity\nChorus:\nPull me closer, hold the moment, let the night begin\nIn the hush between these pages, I can breathe again\n";
+const FALLBACK_LYRICS_SILLY: &str = "Verse 1:\nOn my screen the windows dance, tabs and tasks collide\nShortcut sparks and midnight marks, pixels as my guide\nChorus:\nClick clack, bring the groove back, let the workflow sing\nLaughing through the chaos while I do my thing\n";
+
+// One language's stand-in lyrics in suno-config/lyric_templates.json, keyed by language code
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LyricTemplates {
+    serious: String,
+    silly: Option<String>, // serious is used when missing
+}
+
+fn lyric_templates_path(root: &Path) -> PathBuf { root.join("suno-config").join("lyric_templates.json") }
+
+// Lyrics for a vocal track Claude left without any: the language's template (exact code, then the
+// bare language, so "pt-BR" can use "pt"), else the built-in English ones
+fn fallback_lyrics(root: &Path, language: &str, silly: bool) -> String {
+    let templates: BTreeMap<String, LyricTemplates> = match fs::read_to_string(lyric_templates_path(root)) {
+        Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+            println!("Ignoring lyric_templates.json: {}", e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    };
+    let primary = language.split('-').next().unwrap_or(language);
+    let found = templates
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(language))
+        .or_else(|| templates.iter().find(|(code, _)| code.eq_ignore_ascii_case(primary)));
+    match found {
+        Some((_, t)) if silly => t.silly.clone().unwrap_or_else(|| t.serious.clone()),
+        Some((_, t)) => t.serious.clone(),
+        None => {
+            if !is_english(language) { println!("No fallback lyrics for '{}' in lyric_templates.json, using English", language); }
+            (if silly { FALLBACK_LYRICS_SILLY } else { FALLBACK_LYRICS_SERIOUS }).to_string()
+        }
+    }
+}
+
+// Main Suno API sibling of regenerate_suno_request_json_with_prefs: the same Claude request, mapped onto
+// request.json's GenerateRequest (which supplies model, callBackUrl and default weights) and written back to it
+pub async fn regenerate_request_json_with_prefs(client: &Client, fe_prefs: FrontendPreferences, hint: Option<&ContextHint>) -> Result<crate::suno::GenerateRequest> {
+    let generated = regenerate_suno_request_json_with_prefs(client, fe_prefs.clone(), hint).await?;
+    let root = project_root()?;
+    let path = root.join("suno-config").join("request.json");
+    let base_text = fs::read_to_string(&path).with_context(|| format!("read {} (needed for model and callBackUrl)", path.display()))?;
+    let base: crate::suno::GenerateRequest = serde_json::from_str(&base_text).context("Invalid JSON in request.json")?;
+    let payload = crate::suno::generate_request_from(base, &generated.request, Some(&fe_prefs)).map_err(anyhow::Error::msg)?;
+    fs::write(&path, serde_json::to_string_pretty(&payload)?).context("Failed to write request.json")?;
+    Ok(payload)
+}
+
+fn build_lyrics_prompt(topic: &str, tags: &str, silly: bool, language: &str) -> String {
+    let style = if silly { "SILLY / HUMOROUS (funny, witty, light)" } else { "SERIOUS / PROFESSIONAL (natural, singable, appealing)" };
+    format!(
+        "Write NEW song lyrics for an existing track. The music style is fixed; do not change it.\n\nTrack description: {}\nStyle tags: {}\nLyrics style: {}\nLyrics language: {}\n\nUse the screenshot only as light inspiration for the subject matter.\nRULES:\n- Provide coherent, natural, singable multi-line lyrics with verse/chorus labels.\n- Write every line in the lyrics language.\n- Keep it clean and safe.\n\nPlease provide your response in this exact JSON format:\n{{\n  \"prompt\": \"the full lyrics\"\n}}\nReturn ONLY the JSON, no other text.",
+        topic, tags, style, language_name(language)
+    )
+}
+
+// Keep the last request's topic/tags and ask Claude only for fresh lyrics (always vocal)
+pub async fn regenerate_lyrics_only(client: &Client, fe_prefs: Option<FrontendPreferences>) -> Result<HackmitGenerateReq> {
+    let _ = dotenvy::dotenv();
+    let root = project_root()?;
+    let _ = dotenvy::from_filename(root.join(".env"));
+
+    let last = load_last_request(&root).context("No previous request to reuse; generate a track first")?;
+    let topic = last.topic.clone().unwrap_or_else(|| "Generated track".to_string());
+    let tags = last.tags.clone().unwrap_or_default();
+    let shot = find_latest_screenshot(&root.join("temp"))?;
+    let silly = fe_prefs.as_ref().and_then(|fp| fp.silly_mode).unwrap_or(false);
+    let language = lyrics_language(&load_user_preferences(&root), &fe_prefs);
+    let prompt = build_lyrics_prompt(&topic, &tags, silly, &language);
+
+    let api_key = crate::keys::api_key("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY is not set in the keychain or .env")?;
+    let claude_config = load_claude_config(&root)?;
+    let raw = call_anthropic(client, &api_key, &shot, &prompt, claude_config.generation(false), claude_config.upload()).await?;
+    let json_block = extract_json_block(&raw).context("Claude lyrics response did not contain JSON")?;
+    let v = parse_json_lenient(&json_block, "Claude lyrics JSON")?;
+    let lyrics = as_string(v.get("prompt")).context("Claude lyrics response had no prompt")?;
+
+    let req = HackmitGenerateReq {
+        topic: Some(topic),
+        tags: Some(tags),
+        negative_tags: last.negative_tags.clone(),
+        prompt: Some(lyrics),
+        make_instrumental: Some(false),
+        cover_clip_id: None,
+        reason: last.reason.clone(),
+    };
+    save_request(&root, &req)?;
     Ok(req)
 }
 
 fn recent_genres_path(root: &Path) -> PathBuf { root.join("suno-config").join("recent_genres.json") }
 
-fn load_recent_genres(root: &Path) -> Vec<String> {
+// One entry of recent_genres.json; instrumental is None for entries written before it was tracked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct RecentGenre {
+    genre: String,
+    instrumental: Option<bool>,
+}
+
+impl RecentGenre {
+    fn label(&self) -> String {
+        let mode = match self.instrumental {
+            Some(true) => "instrumental",
+            Some(false) => "vocal",
+            None => "unknown",
+        };
+        format!("{} ({})", self.genre, mode)
+    }
+
+    fn same_as(&self, genre: &str, instrumental: Option<bool>) -> bool {
+        self.genre.eq_ignore_ascii_case(genre) && self.instrumental == instrumental
+    }
+}
+
+// Accepts both the legacy flat string list and the current object entries
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecentGenreEntry {
+    Legacy(String),
+    Current(RecentGenre),
+}
+
+// The most recent genres to name in the prompt (config diversity_mention_count); storage keeps more
+fn mentioned_genres<'a>(root: &Path, recent: &'a [RecentGenre]) -> &'a [RecentGenre] {
+    let count = crate::config::load_config(root).diversity_mention_count;
+    &recent[..recent.len().min(count)]
+}
+
+fn load_recent_genres(root: &Path) -> Vec<RecentGenre> {
     let p = recent_genres_path(root);
     let txt = std::fs::read_to_string(&p).ok();
     if let Some(t) = txt {
         serde_json::from_str::<serde_json::Value>(&t)
             .ok()
             .and_then(|v| v.get("recent").cloned())
-            .and_then(|v| serde_json::from_value::<Vec<String>>(v).ok())
+            .and_then(|v| serde_json::from_value::<Vec<RecentGenreEntry>>(v).ok())
             .unwrap_or_default()
+            .into_iter()
+            .map(|e| match e {
+                RecentGenreEntry::Legacy(genre) => RecentGenre { genre, instrumental: None },
+                RecentGenreEntry::Current(g) => g,
+            })
+            .collect()
     } else { vec![] }
 }
 
-fn save_recent_genres(root: &Path, genres: &Vec<String>) -> Result<()> {
+fn save_recent_genres(root: &Path, genres: &[RecentGenre]) -> Result<()> {
     let p = recent_genres_path(root);
     if let Some(dir) = p.parent() { let _ = std::fs::create_dir_all(dir); }
     let obj = serde_json::json!({ "recent": genres });
@@ -433,6 +1494,129 @@ fn save_recent_genres(root: &Path, genres: &Vec<String>) -> Result<()> {
     Ok(())
 }
 
+// Prepend the primary genres of `tags` (most recent first, unique per genre + mode, max 5)
+fn remember_genres(root: &Path, tags: &str, instrumental: Option<bool>) {
+    let mut current = load_recent_genres(root);
+    for g in extract_primary_genres(tags) {
+        current.retain(|x| !x.same_as(&g, instrumental));
+        current.insert(0, RecentGenre { genre: g, instrumental });
+    }
+    if current.len() > 5 { current.truncate(5); }
+    let _ = save_recent_genres(root, &current);
+}
+
+// Genres a seeded session picks from, mirroring the examples in the diversity rules
+const SEEDED_VOCAL_GENRES: [&str; 11] = ["classical", "pop", "rock", "heavy metal", "jazz", "hip hop", "acoustic", "lofi", "folk", "blues", "world"];
+const SEEDED_INSTRUMENTAL_GENRES: [&str; 8] = ["orchestral", "classical", "acoustic fingerstyle", "post-rock", "jazz trio", "string quartet", "lofi", "piano"];
+
+// set_session_seed override; None falls back to config.json session_seed
+static SESSION_SEED: std::sync::Mutex<Option<u64>> = std::sync::Mutex::new(None);
+
+fn session_seed(root: &Path) -> Option<u64> {
+    let set = *SESSION_SEED.lock().unwrap_or_else(|e| e.into_inner());
+    set.or(crate::config::load_config(root).session_seed)
+}
+
+// FNV-1a, so a seed means the same thing across builds and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Deterministic pick from the seed and the recent-genre state, skipping genres just used in this mode
+fn seeded_genre(seed: u64, recent: &[RecentGenre], instrumental: bool) -> &'static str {
+    let pool: &[&'static str] = if instrumental { &SEEDED_INSTRUMENTAL_GENRES } else { &SEEDED_VOCAL_GENRES };
+    let fresh: Vec<&'static str> = pool
+        .iter()
+        .copied()
+        .filter(|g| !recent.iter().any(|r| r.genre.eq_ignore_ascii_case(g) && r.instrumental != Some(!instrumental)))
+        .collect();
+    let candidates = if fresh.is_empty() { pool.to_vec() } else { fresh };
+    let mut key = seed.to_le_bytes().to_vec();
+    key.push(instrumental as u8);
+    for r in recent {
+        key.extend_from_slice(r.label().as_bytes());
+        key.push(0);
+    }
+    candidates[(fnv1a(&key) % candidates.len() as u64) as usize]
+}
+
+// Make genre choices reproducible for rehearsals; None clears the override (config session_seed applies again)
+#[tauri::command]
+pub async fn set_session_seed(seed: Option<u64>) -> Result<Option<u64>, String> {
+    *SESSION_SEED.lock().unwrap_or_else(|e| e.into_inner()) = seed;
+    let root = project_root().map_err(|e| e.to_string())?;
+    Ok(session_seed(&root))
+}
+
+// User override of the diversity rules until `until_ms` (unix millis), kept in suno-config/pinned_genre.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GenrePin {
+    genre: String,
+    until_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PinStatus {
+    pub genre: String,
+    pub remaining_secs: u64,
+}
+
+fn pin_path(root: &Path) -> PathBuf { root.join("suno-config").join("pinned_genre.json") }
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+impl GenrePin {
+    fn status(&self) -> PinStatus {
+        PinStatus { genre: self.genre.clone(), remaining_secs: self.until_ms.saturating_sub(now_ms()) / 1000 }
+    }
+}
+
+// The pin, unless there is none or it has expired
+fn load_active_pin(root: &Path) -> Option<GenrePin> {
+    let txt = fs::read_to_string(pin_path(root)).ok()?;
+    let pin: GenrePin = serde_json::from_str(&txt).ok()?;
+    (pin.until_ms > now_ms()).then_some(pin)
+}
+
+#[tauri::command]
+pub async fn pin_genre(genre: String, duration_secs: u64) -> Result<PinStatus, String> {
+    let genre = genre.trim().to_string();
+    if genre.is_empty() { return Err("Genre must not be empty".to_string()); }
+    let root = project_root().map_err(|e| e.to_string())?;
+    let pin = GenrePin { genre, until_ms: now_ms().saturating_add(duration_secs.saturating_mul(1000)) };
+    let p = pin_path(&root);
+    if let Some(dir) = p.parent() { let _ = fs::create_dir_all(dir); }
+    let txt = serde_json::to_string_pretty(&pin).map_err(|e| e.to_string())?;
+    fs::write(&p, txt).map_err(|e| format!("Failed writing {}: {}", p.display(), e))?;
+    Ok(pin.status())
+}
+
+#[tauri::command]
+pub async fn unpin_genre() -> Result<(), String> {
+    let root = project_root().map_err(|e| e.to_string())?;
+    match fs::remove_file(pin_path(&root)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_pinned_genre() -> Result<Option<PinStatus>, String> {
+    let root = project_root().map_err(|e| e.to_string())?;
+    Ok(active_pin_status(&root))
+}
+
+pub(crate) fn active_pin_status(root: &Path) -> Option<PinStatus> {
+    load_active_pin(root).map(|p| p.status())
+}
+
+// Instrumental setting a generation would use right now, after frontend > preferences file > default
+pub(crate) fn effective_instrumental(root: &Path, fe_prefs: &Option<FrontendPreferences>) -> bool {
+    resolve_instrumental(&load_user_preferences(root), fe_prefs)
+}
+
 fn extract_primary_genres(tags: &str) -> Vec<String> {
     // Heuristic: take the first 1-2 comma-separated items as primary genres
     let mut v: Vec<String> = tags
@@ -444,3 +1628,305 @@ fn extract_primary_genres(tags: &str) -> Vec<String> {
     if v.len() > 2 { v.truncate(2); }
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefs(make_instrumental: Option<bool>, default_instrumental: Option<bool>) -> Option<UserPreferences> {
+        Some(UserPreferences { make_instrumental, default_instrumental, global_negative_tags: None, lyrics_language: None })
+    }
+
+    fn frontend(instrumental: Option<bool>) -> Option<FrontendPreferences> {
+        Some(FrontendPreferences { instrumental, ..Default::default() })
+    }
+
+    #[test]
+    fn resolve_instrumental_precedence() {
+        let options = [None, Some(true), Some(false)];
+        for fe in options {
+            for make in options {
+                for default in options {
+                    let expected = fe.or(make).or(default).unwrap_or(true);
+                    assert_eq!(
+                        resolve_instrumental(&prefs(make, default), &frontend(fe)),
+                        expected,
+                        "frontend {:?}, make_instrumental {:?}, default_instrumental {:?}",
+                        fe, make, default
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resolve_instrumental_without_any_preferences() {
+        assert!(resolve_instrumental(&None, &None));
+        assert!(!resolve_instrumental(&None, &frontend(Some(false))));
+        assert!(resolve_instrumental(&prefs(None, None), &frontend(None)));
+    }
+
+    #[test]
+    fn json_in_the_second_content_block_is_found() {
+        let body = r#"{
+            "content": [
+                { "type": "thinking", "thinking": "The screen shows an editor..." },
+                { "type": "text", "text": "Here you go:\n{\"topic\": \"Calm focus\", \"tags\": \"lofi, ambient\"}" }
+            ],
+            "stop_reason": "end_turn"
+        }"#;
+        let parsed: AnthropicResponse = serde_json::from_str(body).unwrap();
+        let text = parsed.joined_text().unwrap();
+        let json = extract_json_block(&text).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["topic"], "Calm focus");
+        assert_eq!(value["tags"], "lofi, ambient");
+    }
+
+    // What post_messages makes of a response body
+    fn answer_of(body: &str) -> Result<String> {
+        let parsed: AnthropicResponse = serde_json::from_str(body).unwrap();
+        answer_text(parsed.stop_reason.as_deref(), parsed.joined_text(), " (test)").context("Claude generation failed")
+    }
+
+    #[test]
+    fn refusals_are_classified_through_added_context() {
+        let stopped = answer_of(r#"{"content": [], "stop_reason": "refusal"}"#).unwrap_err();
+        assert_eq!(refusal(&stopped).unwrap().message, "stop_reason: refusal");
+
+        let apology = answer_of(r#"{"content": [{"type": "text", "text": "  I’m sorry, but I can't describe this screenshot. "}], "stop_reason": "end_turn"}"#).unwrap_err();
+        assert_eq!(refusal(&apology).unwrap().message, "I’m sorry, but I can't describe this screenshot.");
+        assert!(low_confidence(&apology).is_none());
+    }
+
+    #[test]
+    fn apologies_around_json_and_empty_answers_are_not_refusals() {
+        let text = answer_of(r#"{"content": [{"type": "text", "text": "Sorry for the wait! {\"topic\": \"Rain\", \"tags\": \"lofi\"}"}], "stop_reason": "end_turn"}"#).unwrap();
+        assert!(text.ends_with(r#"{"topic": "Rain", "tags": "lofi"}"#));
+        let hedged = answer_of(r#"{"content": [{"type": "text", "text": "I cannot be sure, but: {\"topic\": \"Rain\"}"}]}"#);
+        assert!(hedged.is_ok());
+
+        let empty = answer_of(r#"{"content": [], "stop_reason": "end_turn"}"#).unwrap_err();
+        assert!(refusal(&empty).is_none());
+    }
+
+    #[test]
+    fn near_miss_json_is_parsed_leniently() {
+        let commented = r#"{
+            // Claude sometimes annotates its answer
+            "topic": "Rain on the harbor", /* kept short */
+            'tags': "lofi, ambient",
+            negative_tags: "screamo",
+        }"#;
+        let value = parse_json_lenient(commented, "test JSON").unwrap();
+        assert_eq!(value["topic"], "Rain on the harbor");
+        assert_eq!(value["tags"], "lofi, ambient");
+        assert_eq!(value["negative_tags"], "screamo");
+        assert_eq!(parse_json_lenient(r#"{"tags": ["lofi", "jazz",],}"#, "test JSON").unwrap()["tags"][1], "jazz");
+    }
+
+    #[test]
+    fn fenced_reply_with_trailing_commas_builds_a_request() {
+        let raw = "Sure!\n```json\n{\n  \"topic\": \"Late shift\",\n  \"tags\": \"synthwave, night drive\",\n  \"reason\": \"dark IDE\",\n}\n```";
+        let req = parse_claude_request(raw, &None, &None, false, 0.0).unwrap();
+        assert_eq!(req.topic.as_deref(), Some("Late shift"));
+        assert_eq!(req.tags.as_deref(), Some("synthwave, night drive"));
+        assert_eq!(req.reason.as_deref(), Some("dark IDE"));
+    }
+
+    #[test]
+    fn truncated_json_is_an_error_that_goes_to_repair() {
+        let cut = r#"{"topic": "Late shift", "tags": "synthwave, ni"#;
+        let err = parse_json_lenient(cut, "test JSON").unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Failed to parse test JSON"));
+        // Cut off inside a fence and inside a nested value, neither panics; both are plain errors, which ask_claude_for_request repairs
+        for raw in [format!("```json\n{}", cut), r#"{"request": {"topic": "Late shift", "tags": "synthwave"}"#.to_string()] {
+            let err = parse_claude_request(&raw, &None, &None, false, 0.5).unwrap_err();
+            assert!(low_confidence(&err).is_none(), "{:#}", err);
+        }
+    }
+
+    fn write_png(path: &Path, modified: SystemTime) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(4, 4))
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
+            .unwrap();
+        fs::write(path, &bytes).unwrap();
+        set_mtime(path, modified);
+        bytes
+    }
+
+    fn set_mtime(path: &Path, modified: SystemTime) {
+        fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn truncated_newer_screenshot_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let older = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let complete = dir.path().join("complete.png");
+        let bytes = write_png(&complete, older);
+        // Newer, but cut off before the IEND chunk like an interrupted write
+        let truncated = dir.path().join("truncated.png");
+        fs::write(&truncated, &bytes[..bytes.len() - 12]).unwrap();
+        set_mtime(&truncated, older + std::time::Duration::from_secs(5));
+        assert!(!is_complete_image(&truncated));
+        assert_eq!(find_latest_screenshot(dir.path()).unwrap(), complete);
+    }
+
+    #[test]
+    fn equal_mtimes_pick_the_last_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let same = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        // Written in reverse order so creation order can't be what decides
+        write_png(&dir.path().join("shot-b.png"), same);
+        write_png(&dir.path().join("shot-a.png"), same);
+        for _ in 0..3 {
+            assert_eq!(find_latest_screenshot(dir.path()).unwrap(), dir.path().join("shot-b.png"));
+        }
+    }
+
+    #[test]
+    fn misspelled_preference_keys_keep_the_rest_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("sample_preferences.json"),
+            r#"{"make_instrumentl": true, "global_negative_tags": ["screamo"], "lyrics_language": "es"}"#,
+        )
+        .unwrap();
+        let prefs = load_user_preferences(dir.path()).unwrap();
+        assert_eq!(prefs.make_instrumental, None);
+        assert_eq!(prefs.global_negative_tags, Some(vec!["screamo".to_string()]));
+        assert_eq!(prefs.lyrics_language.as_deref(), Some("es"));
+    }
+
+    #[test]
+    fn merge_tags_keeps_a_context_tag_under_many_genres() {
+        let genres: Vec<String> = ["synthwave", "orchestral", "drum and bass", "jazz fusion", "post-rock", "dream pop", "trap", "bossa nova"]
+            .iter()
+            .map(|g| g.to_string())
+            .collect();
+        let context = "focused coding, minimal electronic, steady pulse";
+        for max_len in [20, 30, 50, 100] {
+            let merged = merge_tags(&genres, context, genres.len(), max_len);
+            assert!(merged.chars().count() <= max_len, "{:?} is over {}", merged, max_len);
+            let has_context = ["focused coding", "minimal electronic", "steady pulse"].iter().any(|c| merged.contains(c));
+            assert!(has_context, "no context tag in {:?} (max {})", merged, max_len);
+        }
+    }
+
+    #[test]
+    fn merge_tags_caps_frontend_genres() {
+        let genres: Vec<String> = vec!["jazz".into(), "rock".into(), "metal".into()];
+        assert_eq!(merge_tags(&genres, "ambient, lofi", 1, 100), "jazz, ambient, lofi");
+    }
+
+    // The kept text (without "...") must end where a word of `original` ends
+    fn ends_on_word_boundary(original: &str, shortened: &str) -> bool {
+        let kept = shortened.trim_end_matches("...");
+        original.starts_with(kept) && original[kept.len()..].chars().next().map_or(true, |c| c.is_whitespace() || c == ',' || c == ';')
+    }
+
+    #[test]
+    fn shorten_counts_chars_not_bytes() {
+        let cases = [
+            ("naïve café résumé déjà vu über straße", 20, "naïve café résumé..."),
+            ("日本語の歌詞 とても 長い タイトル です", 12, "日本語の歌詞..."),
+            ("🎵🎶 emoji tags 🎸🥁 everywhere", 15, "🎵🎶 emoji..."),
+        ];
+        for (input, max, expected) in cases {
+            let out = shorten(input, max);
+            assert_eq!(out, expected);
+            assert!(out.chars().count() <= max, "{:?} is over {} chars", out, max);
+            assert!(ends_on_word_boundary(input, &out), "{:?} cuts a word of {:?}", out, input);
+        }
+    }
+
+    #[test]
+    fn shorten_cuts_a_single_long_token() {
+        assert_eq!(shorten("supercalifragilisticexpialidocious", 10), "superca...");
+        assert_eq!(shorten("ünïcödéwördwithoutanyspaces", 8), "ünïcö...");
+        assert_eq!(shorten("ab", 1), "a");
+    }
+
+    #[test]
+    fn shorten_leaves_short_text_alone() {
+        assert_eq!(shorten("lofi, ambient", 100), "lofi, ambient");
+        assert_eq!(shorten("ünïcödé", 7), "ünïcödé");
+    }
+
+    #[test]
+    fn legacy_recent_genres_load_with_unknown_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("suno-config")).unwrap();
+        fs::write(recent_genres_path(dir.path()), r#"{"recent": ["lofi", {"genre": "jazz", "instrumental": true}, "Rock"]}"#).unwrap();
+        let genre = |g: &str, instrumental| RecentGenre { genre: g.to_string(), instrumental };
+        assert_eq!(load_recent_genres(dir.path()), vec![genre("lofi", None), genre("jazz", Some(true)), genre("Rock", None)]);
+
+        // The next write migrates the file; a legacy entry only matches a genre of unknown mode
+        remember_genres(dir.path(), "rock, anthemic", Some(false));
+        let saved: Value = serde_json::from_str(&fs::read_to_string(recent_genres_path(dir.path())).unwrap()).unwrap();
+        assert!(saved["recent"].as_array().unwrap().iter().all(|e| e.is_object()));
+        assert_eq!(
+            load_recent_genres(dir.path()),
+            vec![genre("anthemic", Some(false)), genre("rock", Some(false)), genre("lofi", None), genre("jazz", Some(true)), genre("Rock", None)]
+        );
+    }
+
+    // Genres a seeded session goes through, fed back into recent genres the way remember_genres does
+    fn seeded_progression(seed: u64, tracks: usize) -> Vec<&'static str> {
+        let mut recent: Vec<RecentGenre> = Vec::new();
+        (0..tracks)
+            .map(|_| {
+                let genre = seeded_genre(seed, &recent, false);
+                recent.insert(0, RecentGenre { genre: genre.to_string(), instrumental: Some(false) });
+                recent.truncate(5);
+                genre
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_session_seed_replays_the_same_genres() {
+        let run = seeded_progression(42, 8);
+        assert_eq!(run, seeded_progression(42, 8));
+        for (i, genre) in run.iter().enumerate() {
+            assert!(!run[i.saturating_sub(5)..i].contains(genre), "{} repeats within 5 tracks in {:?}", genre, run);
+        }
+    }
+
+    #[test]
+    fn different_session_seeds_give_different_genres() {
+        assert_ne!(seeded_progression(1, 8), seeded_progression(2, 8));
+        assert_ne!(seeded_progression(42, 8), seeded_progression(43, 8));
+    }
+
+    #[test]
+    fn topic_similarity_ignores_case_punctuation_and_short_words() {
+        assert_eq!(topic_similarity("Late-night CODING, in the zone", "late night coding in the zone"), 1.0);
+        assert_eq!(topic_similarity("rainy harbor at dawn", "neon racing through the city"), 0.0);
+        assert_eq!(topic_similarity("", "a b"), 0.0);
+        // 3 of 5 distinct words shared
+        assert_eq!(topic_similarity("quiet rain over tokyo", "quiet rain over paris"), 0.6);
+    }
+
+    #[test]
+    fn only_topics_at_or_over_the_threshold_are_retried() {
+        let prev = Some("quiet rain over tokyo");
+        assert_eq!(topic_too_similar(prev, Some("quiet rain over paris")), Some(0.6));
+        assert_eq!(topic_too_similar(prev, Some("QUIET RAIN OVER TOKYO!")), Some(1.0));
+        // 2 of 6 shared, well under 0.6
+        assert_eq!(topic_too_similar(prev, Some("quiet rain, desert highway")), None);
+        assert_eq!(topic_too_similar(None, Some("quiet rain over tokyo")), None);
+        assert_eq!(topic_too_similar(prev, None), None);
+    }
+
+    #[test]
+    fn context_hints_are_capped_before_they_reach_the_prompt() {
+        let hint = ContextHint::Blend("a".repeat(50), "docs".to_string());
+        let prompt = with_context_hint(String::new(), Some(&hint), 10);
+        assert!(prompt.contains(&format!("'{}…'", "a".repeat(9))));
+        assert!(!prompt.contains(&"a".repeat(10)));
+        assert!(prompt.contains("'docs'"));
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    #[default]
+    Screen, // whole display under the cursor
+    Window, // frontmost window only (macOS), falls back to Screen
+}
+
+// Which tag a tick's decision uses when tag_smoothing_window > 1
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSmoothing {
+    #[default]
+    Latest,   // the newest classification, as without smoothing
+    Majority, // the most frequent tag of the window; ties go to the most recent
+}
+
+// App-level settings read from suno-config/config.json; every field is optional
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub capture: CaptureMode,
+    pub capture_interval_secs: f64, // time between periodic captures, at least MIN_CAPTURE_INTERVAL_SECS
+    pub startup_grace_secs: u64, // decisions are "observe" only (no generation) for this long after launch
+    pub settle_ticks: u32, // quiet ticks in a row before the first classification after launch; 0 = classify the first frame
+    pub max_frontend_genres: usize, // how many UI-selected genres are mixed into the tags
+    pub min_track_interval_secs: u64, // hard floor between switches; changes inside it are queued
+    pub track_interval_fraction: f64, // once a track's length is known the floor grows to this share of it...
+    pub max_track_interval_secs: u64, // ...capped here
+    pub fade_ms: u64, // base crossfade for switches, scaled by how big the change was
+    pub blocklist_apps: Vec<String>, // frontmost apps (case-insensitive substrings) that are never captured
+    pub allowlist_apps: Vec<String>, // when non-empty, periodic captures only run in these apps; the blocklist still wins
+    pub suno_model: Option<String>, // main Suno API model override; request.json's value when unset
+    pub diversity_mention_count: usize, // how many recent genres the prompt names (up to the 5 stored)
+    pub debug_capture: bool, // save every raw Claude response under suno-config/debug
+    pub reject_concurrent_generations: bool, // fail a second generation instead of waiting for the running one
+    pub generation_queue_depth: usize, // periodic generations allowed to wait behind the running one
+    pub keep_screenshots: usize, // newest images kept in temp/, older ones are deleted (at least 1 is kept)
+    pub session_budget_usd: Option<f64>, // spend:budget_exceeded fires once the estimated Claude spend passes this
+    pub suno_credits_per_generation: i64, // deducted from the Suno credit estimate per submitted task
+    pub session_seed: Option<u64>, // makes the suggested genre progression reproducible; set_session_seed overrides it
+    pub instrumental_by_cognitive_load: bool, // high load forces instrumental, low load allows vocals, whatever the default
+    pub app_tags: BTreeMap<String, String>, // frontmost app name (case-insensitive) -> context tag for local classification
+    pub local_classification: bool, // classify apps in app_tags locally instead of asking Claude
+    pub tag_smoothing: TagSmoothing,
+    pub tag_smoothing_window: usize, // recent classifications considered; 1 = no smoothing
+    pub blend_window_secs: u64, // A,B,A,B context changes within this many seconds get one blended track; 0 = off
+    pub grayscale: bool, // send Claude a grayscale image (smaller upload); change detection still sees color
+    pub contrast: f32, // contrast adjustment for the image sent to Claude, e.g. 20.0 or -10.0; 0 = off
+    pub max_image_width: u32, // wider captures are scaled down (aspect kept) before they're sent to Claude; 0 = native size
+    pub min_generation_confidence: f64, // skip Suno when Claude's confidence in its read of the screen (0-1) is below this; 0 = off
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            capture: CaptureMode::Screen,
+            capture_interval_secs: 5.0,
+            startup_grace_secs: 10,
+            settle_ticks: 2,
+            max_frontend_genres: 2,
+            min_track_interval_secs: 60,
+            track_interval_fraction: 0.75,
+            max_track_interval_secs: 180,
+            fade_ms: 1500,
+            blocklist_apps: Vec::new(),
+            allowlist_apps: Vec::new(),
+            suno_model: None,
+            diversity_mention_count: 3,
+            debug_capture: false,
+            reject_concurrent_generations: false,
+            generation_queue_depth: 1,
+            keep_screenshots: 20,
+            session_budget_usd: None,
+            suno_credits_per_generation: 10,
+            session_seed: None,
+            instrumental_by_cognitive_load: false,
+            app_tags: default_app_tags(),
+            local_classification: false,
+            tag_smoothing: TagSmoothing::Latest,
+            tag_smoothing_window: 1,
+            blend_window_secs: 0,
+            grayscale: false,
+            contrast: 0.0,
+            max_image_width: 2560,
+            min_generation_confidence: 0.0,
+        }
+    }
+}
+
+fn default_app_tags() -> BTreeMap<String, String> {
+    [
+        ("Code", "vscode-coding"),
+        ("Visual Studio Code", "vscode-coding"),
+        ("Xcode", "xcode-coding"),
+        ("Google Chrome", "chrome-browsing"),
+        ("Safari", "safari-browsing"),
+        ("Firefox", "firefox-browsing"),
+        ("Terminal", "terminal"),
+        ("iTerm2", "terminal"),
+        ("Slack", "slack-chat"),
+        ("Figma", "figma-design"),
+        ("Notion", "notion-docs"),
+    ]
+    .into_iter()
+    .map(|(app, tag)| (app.to_string(), tag.to_string()))
+    .collect()
+}
+
+// Manual capture trigger, read from suno-config/hotkeys.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub trigger: Option<String>, // e.g. "CommandOrControl+Shift+M"; no hotkey is registered when unset
+    pub manual_only: bool, // skip the 5s timer and only capture when the hotkey is pressed
+}
+
+pub(crate) const MIN_CAPTURE_INTERVAL_SECS: f64 = 0.5;
+
+// Clamp a requested capture interval to something the loop can run at
+pub(crate) fn capture_interval(secs: f64) -> std::time::Duration {
+    let secs = if secs.is_finite() { secs.max(MIN_CAPTURE_INTERVAL_SECS) } else { MIN_CAPTURE_INTERVAL_SECS };
+    std::time::Duration::from_secs_f64(secs)
+}
+
+fn config_path(root: &Path, file: &str) -> PathBuf { root.join("suno-config").join(file) }
+
+// Missing file => defaults; malformed file => defaults plus a log line
+fn load_or_default<T: serde::de::DeserializeOwned + Default>(path: &Path) -> T {
+    let txt = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(_) => return T::default(),
+    };
+    match serde_json::from_str(&txt) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("Invalid {}: {}, using defaults", path.display(), e);
+            T::default()
+        }
+    }
+}
+
+pub(crate) fn load_config(root: &Path) -> AppConfig {
+    load_or_default(&config_path(root, "config.json"))
+}
+
+pub(crate) fn load_hotkeys(root: &Path) -> HotkeyConfig {
+    load_or_default(&config_path(root, "hotkeys.json"))
+}
+
+// API host from env var `var`, falling back to `default`; trailing slashes are dropped
+pub(crate) fn base_url(var: &str, default: &str) -> String {
+    match std::env::var(var) {
+        Ok(v) if !v.trim().is_empty() => v.trim().trim_end_matches('/').to_string(),
+        _ => default.to_string(),
+    }
+}
+
+// Every setting the app is running with, after files, env vars, UI preferences and defaults are merged
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveConfig {
+    pub root: PathBuf,
+    pub root_source: String, // how the root was found: PackageJson, DataDirEnv, CurrentDir or ExeDir
+    pub app: AppConfig,
+    pub hotkeys: HotkeyConfig,
+    pub claude: crate::claude::ClaudeConfig,
+    pub capture_interval_secs: f64, // in use now, including set_capture_interval changes
+    pub change_threshold: u32, // hash distance above which a frame counts as a context change
+    pub max_hash_distance: u32,
+    pub instrumental: bool, // what the next generation would use
+    pub frontend_preferences: Option<crate::claude::FrontendPreferences>,
+    pub pinned_genre: Option<crate::claude::PinStatus>,
+    pub focus_lock_remaining_secs: Option<u64>,
+    pub suno_models: Vec<String>,
+    pub anthropic_base_url: String,
+    pub suno_base_url: String,
+    pub hackmit_base_url: String,
+}
+
+#[tauri::command]
+pub async fn get_effective_config(
+    app: tauri::AppHandle,
+    fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>,
+    control: tauri::State<'_, crate::control::PipelineControl>,
+) -> Result<EffectiveConfig, String> {
+    use tauri::Manager;
+    let (root, strategy) = crate::claude::locate_project_root().map_err(|e| e.to_string())?;
+    let app_config = load_config(&root);
+    let capture_interval_secs = match app.try_state::<crate::screenshot::Pipeline>() {
+        Some(pipeline) => pipeline.capture_interval_secs(),
+        None => capture_interval(app_config.capture_interval_secs).as_secs_f64(),
+    };
+    let frontend_preferences = fe_prefs.0.lock().await.clone();
+    Ok(EffectiveConfig {
+        root_source: format!("{:?}", strategy),
+        hotkeys: load_hotkeys(&root),
+        claude: crate::claude::load_claude_config(&root).map_err(|e| e.to_string())?,
+        capture_interval_secs,
+        change_threshold: crate::screenshot::THRESHOLD_DISTANCE,
+        max_hash_distance: crate::screenshot::MAX_HASH_DISTANCE,
+        instrumental: crate::claude::effective_instrumental(&root, &frontend_preferences),
+        pinned_genre: crate::claude::active_pin_status(&root),
+        focus_lock_remaining_secs: control.focus_remaining().map(|d| d.as_secs()),
+        suno_models: crate::suno::SUNO_MODELS.iter().map(|m| m.to_string()).collect(),
+        anthropic_base_url: crate::claude::anthropic_base_url(),
+        suno_base_url: crate::suno::suno_base_url(),
+        hackmit_base_url: crate::suno::hackmit_base_url(),
+        frontend_preferences,
+        app: app_config,
+        root,
+    })
+}
@@ -0,0 +1,126 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+// Runtime output rather than configuration; never exported
+const SKIPPED: [&str; 2] = ["session.jsonl", "debug"];
+const ENV_FILE: &str = ".env";
+
+fn config_dir(root: &Path) -> PathBuf { root.join("suno-config") }
+
+// Every file under `dir` as (path relative to `dir` with '/' separators, absolute path)
+fn collect_files(dir: &Path, prefix: &str, out: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let rel = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, &rel, out)?;
+        } else {
+            out.push((rel, path));
+        }
+    }
+    Ok(())
+}
+
+fn write_archive(root: &Path, dest: &Path, include_env: bool) -> Result<Vec<String>> {
+    let dir = config_dir(root);
+    let mut files = Vec::new();
+    collect_files(&dir, "", &mut files)?;
+    files.retain(|(rel, _)| {
+        let top = rel.split('/').next().unwrap_or("");
+        !SKIPPED.contains(&top) && (include_env || top != ENV_FILE)
+    });
+    files.sort();
+
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut zip = zip::ZipWriter::new(File::create(dest).with_context(|| format!("create {}", dest.display()))?);
+    for (rel, path) in &files {
+        let bytes = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        zip.start_file(rel.as_str(), options)?;
+        zip.write_all(&bytes)?;
+    }
+    zip.finish()?;
+    Ok(files.into_iter().map(|(rel, _)| rel).collect())
+}
+
+// Problems with one archived file, checked the same way the app will load it
+fn check_file(name: &str, bytes: &[u8]) -> Vec<String> {
+    if !name.ends_with(".json") { return vec![]; }
+    let Ok(text) = std::str::from_utf8(bytes) else { return vec![format!("{}: not UTF-8 text", name)] };
+    let kind = match name {
+        "request.json" => Some("suno_request"),
+        "suno_request.json" => Some("hackmit_request"),
+        _ => None,
+    };
+    let problems: Vec<String> = match (name, kind) {
+        (_, Some(kind)) => crate::prefs_schema::validate_preferences(text.to_string(), Some(kind.to_string())).unwrap_or_else(|e| vec![e]),
+        ("config.json", _) => serde_json::from_str::<crate::config::AppConfig>(text).err().map(|e| e.to_string()).into_iter().collect(),
+        ("hotkeys.json", _) => serde_json::from_str::<crate::config::HotkeyConfig>(text).err().map(|e| e.to_string()).into_iter().collect(),
+        ("intent_map.json", _) => serde_json::from_str::<std::collections::BTreeMap<String, String>>(text).err().map(|e| e.to_string()).into_iter().collect(),
+        ("lyric_templates.json", _) => serde_json::from_str::<std::collections::BTreeMap<String, crate::claude::LyricTemplates>>(text)
+            .err()
+            .map(|e| e.to_string())
+            .into_iter()
+            .collect(),
+        ("claude.json", _) => match serde_json::from_str::<crate::claude::ClaudeConfig>(text) {
+            Ok(cfg) => crate::claude::check_claude_config(&cfg).err().map(|e| e.to_string()).into_iter().collect(),
+            Err(e) => vec![e.to_string()],
+        },
+        _ => serde_json::from_str::<serde_json::Value>(text).err().map(|e| format!("not valid JSON: {}", e)).into_iter().collect(),
+    };
+    problems.into_iter().map(|p| format!("{}: {}", name, p)).collect()
+}
+
+// All-or-nothing: every file is read and checked before anything in suno-config is overwritten
+fn read_archive(root: &Path, src: &Path) -> Result<Vec<String>> {
+    let mut zip = zip::ZipArchive::new(File::open(src).with_context(|| format!("open {}", src.display()))?)
+        .context("not a zip archive")?;
+    let mut files: Vec<(PathBuf, String, Vec<u8>)> = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() { continue; }
+        let Some(rel) = entry.enclosed_name() else { bail!("unsafe path in archive: {}", entry.name()) };
+        let name = entry.name().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        files.push((rel, name, bytes));
+    }
+    if files.is_empty() { bail!("archive is empty"); }
+
+    let problems: Vec<String> = files.iter().flat_map(|(_, name, bytes)| check_file(name, bytes)).collect();
+    if !problems.is_empty() {
+        bail!("nothing was imported:\n{}", problems.join("\n"));
+    }
+
+    let dir = config_dir(root);
+    for (rel, _, bytes) in &files {
+        let dest = dir.join(rel);
+        if let Some(parent) = dest.parent() { fs::create_dir_all(parent)?; }
+        fs::write(&dest, bytes).with_context(|| format!("write {}", dest.display()))?;
+    }
+    Ok(files.into_iter().map(|(_, name, _)| name).collect())
+}
+
+// Zip suno-config for another machine; `.env` (API keys) only when include_env is true
+#[tauri::command]
+pub async fn export_config(dest: String, include_env: Option<bool>) -> Result<Vec<String>, String> {
+    let root = crate::claude::project_root().map_err(|e| e.to_string())?;
+    let include_env = include_env.unwrap_or(false);
+    tokio::task::spawn_blocking(move || write_archive(&root, Path::new(&dest), include_env))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Export failed: {:#}", e))
+}
+
+// Unpack an export_config archive into suno-config, overwriting same-named files; returns the files written
+#[tauri::command]
+pub async fn import_config(src: String) -> Result<Vec<String>, String> {
+    let root = crate::claude::project_root().map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || read_archive(&root, Path::new(&src)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Import failed: {:#}", e))
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager};
+use tokio::sync::{MutexGuard, Notify};
+
+use crate::screenshot::{current_app_summary, DecisionEvent};
+
+// Presenter kill switch: suspends the periodic pipeline and cancels in-flight generations
+#[derive(Default)]
+pub struct PipelineControl {
+    stopped: AtomicBool,
+    stop_signal: Notify,
+    focus_until: Mutex<Option<Instant>>, // focus lock: keep observing, never switch
+    generation: tokio::sync::Mutex<()>, // held for a whole Claude + Suno generation
+}
+
+impl PipelineControl {
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    // Time left on the focus lock, None when not locked
+    pub fn focus_remaining(&self) -> Option<Duration> {
+        let until = (*self.focus_until.lock().unwrap_or_else(|e| e.into_inner()))?;
+        until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    }
+
+    // The generation slot if nothing is generating right now, without waiting
+    pub(crate) fn try_generation_slot(&self) -> Option<MutexGuard<'_, ()>> {
+        self.generation.try_lock().ok()
+    }
+
+    // Suspend the periodic pipeline and cancel in-flight generations (stop_all, app exit)
+    pub(crate) fn halt(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.stop_signal.notify_waiters();
+    }
+
+    // Resolves once stop_all is called, immediately if already stopped
+    async fn cancelled(&self) {
+        let notified = self.stop_signal.notified();
+        if self.is_stopped() { return; }
+        notified.await;
+    }
+}
+
+// One generation at a time so two runs can't race on suno_request.json or double-spend credits.
+// Waits for the running one, or with `reject_concurrent_generations` fails fast and emits suno:busy.
+pub(crate) async fn generation_slot<'a>(app: &tauri::AppHandle, control: &'a PipelineControl) -> Result<MutexGuard<'a, ()>, String> {
+    let reject = crate::claude::project_root()
+        .map(|root| crate::config::load_config(&root).reject_concurrent_generations)
+        .unwrap_or(false);
+    if !reject {
+        return Ok(control.generation.lock().await);
+    }
+    control.generation.try_lock().map_err(|_| {
+        let _ = app.emit("suno:busy", ());
+        "generation already in progress".to_string()
+    })
+}
+
+// Run `fut` unless the pipeline is stopped; stop_all drops it mid-flight
+pub(crate) async fn unless_stopped<T>(control: &PipelineControl, fut: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    if control.is_stopped() { return Err("Generation stopped".to_string()); }
+    tokio::select! {
+        res = fut => res,
+        _ = control.cancelled() => Err("Generation stopped".to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn stop_all(app: tauri::AppHandle, control: tauri::State<'_, PipelineControl>) -> Result<(), String> {
+    control.halt();
+    let evt = DecisionEvent {
+        current_context: current_app_summary(),
+        previous_context: None,
+        is_similar: true,
+        action: "stopped".to_string(),
+        fade_ms: None,
+        chosen_tags: None,
+        chosen_topic: None,
+        hint: None,
+        blended_context: None,
+    };
+    match app.try_state::<crate::screenshot::Pipeline>() {
+        Some(pipeline) => pipeline.publish_decision(evt),
+        None => { let _ = app.emit("context:decision", &evt); }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resume_all(control: tauri::State<'_, PipelineControl>) -> Result<(), String> {
+    control.stopped.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn focus_lock(control: tauri::State<'_, PipelineControl>, duration_secs: u64) -> Result<u64, String> {
+    *control.focus_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + Duration::from_secs(duration_secs));
+    Ok(duration_secs)
+}
+
+#[tauri::command]
+pub async fn focus_unlock(control: tauri::State<'_, PipelineControl>) -> Result<(), String> {
+    *control.focus_until.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    Ok(())
+}
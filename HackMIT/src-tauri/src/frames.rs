@@ -0,0 +1,87 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::CaptureMode;
+
+// Replays the images in this directory (sorted by name, looping) instead of capturing the screen
+pub(crate) const FRAME_DIR_ENV: &str = "HACKMIT_FRAME_DIR";
+
+// One captured frame, RGBA8
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+    pub monitor_index: Option<usize>, // position in Screen::all(); None for replayed frames
+}
+
+// Where the pipeline gets its frames from; called once per capture (timer tick or hotkey)
+pub(crate) trait FrameSource: Send + Sync {
+    fn next_frame(&self) -> Result<Frame>;
+}
+
+// Live capture of the display under the cursor, or the frontmost window
+pub(crate) struct ScreenFrameSource {
+    pub mode: CaptureMode,
+}
+
+impl FrameSource for ScreenFrameSource {
+    fn next_frame(&self) -> Result<Frame> {
+        let (img, monitor_index) = crate::screenshot::grab_display(self.mode)?;
+        let (width, height) = img.dimensions();
+        Ok(Frame { width, height, rgba: img.into_raw(), monitor_index })
+    }
+}
+
+// Scripted frames for reproducible demos and for exercising the decision logic without a screen
+pub(crate) struct DirectoryFrameSource {
+    files: Vec<PathBuf>,
+    next: AtomicUsize,
+}
+
+impl DirectoryFrameSource {
+    pub(crate) fn new(dir: &Path) -> Result<Self> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("read {}", dir.display()))?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "png" | "jpg" | "jpeg" | "webp"))
+            })
+            .collect();
+        if files.is_empty() {
+            bail!("no png, jpg or webp images in {}", dir.display());
+        }
+        files.sort();
+        Ok(Self { files, next: AtomicUsize::new(0) })
+    }
+}
+
+impl FrameSource for DirectoryFrameSource {
+    fn next_frame(&self) -> Result<Frame> {
+        let i = self.next.fetch_add(1, Ordering::SeqCst) % self.files.len();
+        let path = &self.files[i];
+        let img = image::open(path).with_context(|| format!("decode {}", path.display()))?.to_rgba8();
+        let (width, height) = img.dimensions();
+        Ok(Frame { width, height, rgba: img.into_raw(), monitor_index: None })
+    }
+}
+
+// HACKMIT_FRAME_DIR picks the directory replay; otherwise (or if it can't be used) the live screen
+pub(crate) fn from_env(mode: CaptureMode) -> Box<dyn FrameSource> {
+    let Some(dir) = std::env::var_os(FRAME_DIR_ENV).filter(|d| !d.is_empty()) else {
+        return Box::new(ScreenFrameSource { mode });
+    };
+    match DirectoryFrameSource::new(Path::new(&dir)) {
+        Ok(src) => {
+            println!("Replaying {} frame(s) from {} instead of capturing the screen", src.files.len(), Path::new(&dir).display());
+            Box::new(src)
+        }
+        Err(e) => {
+            println!("{} unusable ({:#}), capturing the screen", FRAME_DIR_ENV, e);
+            Box::new(ScreenFrameSource { mode })
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use tauri::{Listener, Manager};
+use tokio::sync::broadcast;
+
+use crate::screenshot::Pipeline;
+
+// `--headless` (or HACKMIT_HEADLESS=1) runs the engine with no window: capture, decisions and
+// generation as usual, driven by suno-config, with this module standing in for the webview
+const HEADLESS_FLAG: &str = "--headless";
+const HEADLESS_ENV: &str = "HACKMIT_HEADLESS";
+
+pub(crate) fn requested() -> bool {
+    std::env::args().skip(1).any(|a| a == HEADLESS_FLAG)
+        || std::env::var(HEADLESS_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+// Log every decision from the broadcast channel and download each new track into temp/audio, where the
+// local audio server serves it. There's no audio output without the webview; any player can open the URL.
+pub(crate) fn attach(app: &tauri::AppHandle) {
+    let mut decisions = app.state::<Pipeline>().subscribe_decisions();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match decisions.recv().await {
+                Ok(evt) => match &evt.chosen_tags {
+                    Some(tags) => println!("[headless] {}: {} -> {}", evt.action, evt.current_context.tag, tags),
+                    None => println!("[headless] {}: {}", evt.action, evt.current_context.tag),
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => println!("[headless] {} decision(s) missed", missed),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let handle = app.clone();
+    app.listen_any("music:switch", move |event| {
+        let Ok(url) = serde_json::from_str::<String>(event.payload()) else { return };
+        let app = handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let client = app.state::<reqwest::Client>();
+            match crate::suno::download_clip(&app, &client, &url, None).await {
+                Ok(local) => println!("[headless] New track: {}", local),
+                Err(e) => println!("[headless] New track at {} (not downloaded: {})", url, e),
+            }
+        });
+    });
+    println!("Running headless: no window, decisions and tracks are logged here");
+}
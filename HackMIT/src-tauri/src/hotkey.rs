@@ -0,0 +1,21 @@
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+// Register the manual capture hotkey from suno-config/hotkeys.json, if one is configured
+pub fn register(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    let root = crate::claude::project_root().unwrap_or(std::env::current_dir()?);
+    let Some(combo) = crate::config::load_hotkeys(&root).trigger else { return Ok(()) };
+    let shortcut: Shortcut = combo.parse().map_err(|e| format!("Invalid hotkey '{}': {}", combo, e))?;
+
+    app.handle().plugin(
+        tauri_plugin_global_shortcut::Builder::new()
+            .with_handler(move |app, pressed, event| {
+                if *pressed == shortcut && event.state() == ShortcutState::Pressed {
+                    crate::screenshot::trigger_manual_cycle(app);
+                }
+            })
+            .build(),
+    )?;
+    app.global_shortcut().register(shortcut)?;
+    println!("Manual capture hotkey: {}", combo);
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+// The one HTTP client for Anthropic and Suno, created at startup and kept in Tauri state.
+// reqwest::Client is a handle to a shared pool, so reusing it keeps TLS connections warm
+// across the poll loops instead of handshaking on every call.
+pub(crate) fn shared_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(120)) // long Claude generations stay well under this
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(4)
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .unwrap_or_else(|e| {
+            println!("Falling back to default HTTP client: {}", e);
+            reqwest::Client::new()
+        })
+}
@@ -0,0 +1,38 @@
+// API keys: the OS keychain (set via set_api_key) wins, then the environment / .env files
+const KEYCHAIN_SERVICE: &str = "hackmit";
+
+const KEY_NAMES: [&str; 2] = ["ANTHROPIC_API_KEY", "SUNO_API_KEY"];
+
+fn keychain_entry(name: &str) -> Option<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, name).ok()
+}
+
+// Callers load their .env files first; a missing or unreadable keychain entry just falls through
+pub(crate) fn api_key(name: &str) -> Option<String> {
+    let from_keychain = keychain_entry(name)
+        .and_then(|e| e.get_password().ok())
+        .filter(|v| !v.trim().is_empty());
+    from_keychain.or_else(|| std::env::var(name).ok().filter(|v| !v.trim().is_empty()))
+}
+
+// Store `value` in the keychain under `name`; an empty value removes the stored key
+#[tauri::command]
+pub async fn set_api_key(name: String, value: String) -> Result<(), String> {
+    if !KEY_NAMES.contains(&name.as_str()) {
+        return Err(format!("Unknown key '{}' (expected {})", name, KEY_NAMES.join(" or ")));
+    }
+    tokio::task::spawn_blocking(move || {
+        let entry = keyring::Entry::new(KEYCHAIN_SERVICE, &name).map_err(|e| e.to_string())?;
+        let value = value.trim();
+        if value.is_empty() {
+            match entry.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(format!("Failed to remove {} from the keychain: {}", name, e)),
+            }
+        } else {
+            entry.set_password(value).map_err(|e| format!("Failed to store {} in the keychain: {}", name, e))
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
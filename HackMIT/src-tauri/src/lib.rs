@@ -4,30 +4,120 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+mod audio_server;
+mod config;
+mod config_transfer;
+mod control;
+mod frames;
+mod headless;
+#[cfg(desktop)]
+mod hotkey;
+mod http;
+mod keys;
+mod metrics;
+mod prefs_schema;
 mod suno;
 mod claude;
 mod screenshot;
+mod session;
+mod spend;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    use tauri::Manager;
+    let headless = headless::requested();
+    let mut context = tauri::generate_context!();
+    if headless {
+        context.config_mut().app.windows.clear();
+    }
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .setup(|app| {
+        .manage(http::shared_client())
+        .manage(claude::FrontendPrefsState::default())
+        .manage(control::PipelineControl::default())
+        .manage(metrics::Metrics::default())
+        .setup(move |app| {
+            // No dock icon or menu bar when there's no window
+            #[cfg(target_os = "macos")]
+            if headless {
+                app.set_activation_policy(tauri::ActivationPolicy::Accessory);
+            }
+            // Each launch starts a fresh session timeline
+            if let Ok(root) = crate::claude::project_root() {
+                crate::session::start(&root);
+            }
+            crate::spend::attach(app.handle().clone());
             // kick off periodic screenshot + context decision task
             let handle = app.handle().clone();
             crate::screenshot::start_periodic_task(handle);
+            if headless {
+                crate::headless::attach(app.handle());
+            }
+            // Local playback of downloaded clips (suno_download_clip)
+            let audio_dir = crate::claude::project_root().unwrap_or_else(|_| std::env::temp_dir()).join("temp").join("audio");
+            match crate::audio_server::AudioServer::start(audio_dir) {
+                Ok(server) => { app.manage(server); }
+                Err(e) => println!("Audio server unavailable: {:#}", e),
+            }
+            #[cfg(desktop)]
+            if let Err(e) = crate::hotkey::register(app) {
+                println!("Hotkey registration failed: {}", e);
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             greet,
             suno::suno_generate_from_file,
             suno::suno_generate_and_wait,
+            suno::regenerate_request_json,
+            suno::suno_generate_track_and_wait,
             suno::suno_get_credits,
+            suno::suno_account_info,
+            suno::suno_hackmit_check,
+            suno::suno_check_status,
             suno::suno_hackmit_generate_and_wait,
             suno::suno_hackmit_generate_and_wait_with_prefs,
             suno::suno_generate_from_latest_screenshot_with_prefs,
-            suno::get_current_music_tags
+            suno::suno_hackmit_generate_clips,
+            suno::suno_generate_custom,
+            suno::get_current_music_tags,
+            suno::regenerate_lyrics,
+            suno::suno_extend_clip,
+            suno::suno_download_clip,
+            claude::set_frontend_preferences,
+            claude::pin_genre,
+            claude::unpin_genre,
+            claude::get_pinned_genre,
+            claude::set_session_seed,
+            control::stop_all,
+            control::resume_all,
+            control::focus_lock,
+            control::focus_unlock,
+            config::get_effective_config,
+            metrics::get_metrics,
+            spend::get_spend_summary,
+            keys::set_api_key,
+            screenshot::check_screen_permission,
+            screenshot::capture_preview,
+            screenshot::set_capture_interval,
+            screenshot::get_last_decision_debug,
+            screenshot::reset_state,
+            screenshot::correct_classification,
+            screenshot::classify_with_hint,
+            prefs_schema::preferences_schema,
+            prefs_schema::validate_preferences,
+            session::export_session,
+            config_transfer::export_config,
+            config_transfer::import_config
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<control::PipelineControl>().halt();
+                if let Some(server) = app.try_state::<audio_server::AudioServer>() {
+                    server.shutdown();
+                }
+            }
+        });
 }
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Rolling window per stage; old samples fall off so averages track recent behavior
+const WINDOW: usize = 100;
+
+pub(crate) const STAGE_CAPTURE: &str = "capture";
+pub(crate) const STAGE_HASH: &str = "hash";
+pub(crate) const STAGE_CLAUDE: &str = "claude";
+pub(crate) const STAGE_CLASSIFY: &str = "classify";
+pub(crate) const STAGE_SUNO: &str = "suno";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStats {
+    pub count: u64,     // total samples ever recorded
+    pub last_ms: f64,
+    pub avg_ms: f64,    // over the rolling window
+    pub p95_ms: f64,    // over the rolling window
+}
+
+#[derive(Default)]
+struct Samples {
+    count: u64,
+    recent: VecDeque<f64>,
+}
+
+// Per-stage latency of the capture -> hash -> Claude -> Suno pipeline
+#[derive(Default)]
+pub struct Metrics {
+    stages: Mutex<BTreeMap<&'static str, Samples>>,
+}
+
+impl Metrics {
+    pub fn record(&self, stage: &'static str, elapsed: Duration) {
+        let mut stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+        let samples = stages.entry(stage).or_default();
+        samples.count += 1;
+        samples.recent.push_back(elapsed.as_secs_f64() * 1000.0);
+        if samples.recent.len() > WINDOW { samples.recent.pop_front(); }
+    }
+
+    // Mean over the rolling window; None until the stage has a sample
+    pub(crate) fn average(&self, stage: &str) -> Option<Duration> {
+        let stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+        let samples = stages.get(stage).filter(|s| !s.recent.is_empty())?;
+        let avg_ms = samples.recent.iter().sum::<f64>() / samples.recent.len() as f64;
+        Some(Duration::from_secs_f64(avg_ms / 1000.0))
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, StageStats> {
+        let stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+        stages
+            .iter()
+            .filter(|(_, s)| !s.recent.is_empty())
+            .map(|(name, s)| {
+                let mut sorted: Vec<f64> = s.recent.iter().copied().collect();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let p95_idx = ((sorted.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+                let stats = StageStats {
+                    count: s.count,
+                    last_ms: *s.recent.back().unwrap_or(&0.0),
+                    avg_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+                    p95_ms: sorted[p95_idx.min(sorted.len() - 1)],
+                };
+                (name.to_string(), stats)
+            })
+            .collect()
+    }
+}
+
+// Await `fut` and record how long it took under `stage`
+pub(crate) async fn timed<T>(metrics: &Metrics, stage: &'static str, fut: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let out = fut.await;
+    metrics.record(stage, started.elapsed());
+    out
+}
+
+#[tauri::command]
+pub async fn get_metrics(metrics: tauri::State<'_, Metrics>) -> Result<BTreeMap<String, StageStats>, String> {
+    Ok(metrics.snapshot())
+}
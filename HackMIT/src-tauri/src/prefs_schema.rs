@@ -0,0 +1,94 @@
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::claude::{FrontendPreferences, HackmitGenerateReq, UserPreferences};
+use crate::suno::GenerateRequest;
+
+// Which config shape a JSON document is checked against
+fn schema_and_check(kind: &str) -> Result<(RootSchema, fn(Value) -> Result<(), String>), String> {
+    fn check<T: DeserializeOwned>(v: Value) -> Result<(), String> {
+        serde_json::from_value::<T>(v).map(|_| ()).map_err(|e| e.to_string())
+    }
+    match kind {
+        "user" => Ok((schema_for!(UserPreferences), check::<UserPreferences>)),
+        "frontend" => Ok((schema_for!(FrontendPreferences), check::<FrontendPreferences>)),
+        "hackmit_request" => Ok((schema_for!(HackmitGenerateReq), check::<HackmitGenerateReq>)),
+        "suno_request" => Ok((schema_for!(GenerateRequest), check::<GenerateRequest>)),
+        other => Err(format!("Unknown preferences kind '{}' (expected user, frontend, hackmit_request or suno_request)", other)),
+    }
+}
+
+fn known_keys(schema: &RootSchema) -> Vec<String> {
+    schema
+        .schema
+        .object
+        .as_ref()
+        .map(|o| o.properties.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// Closest known key, if it's near enough to be a plausible typo
+fn suggest<'a>(key: &str, known: &'a [String]) -> Option<&'a str> {
+    let lower = key.to_lowercase();
+    known
+        .iter()
+        .map(|k| (edit_distance(&lower, k), k))
+        .filter(|(d, k)| *d <= 3.max(k.len() / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k.as_str())
+}
+
+// JSON Schema for one of the preference/request shapes, e.g. for editor autocompletion
+#[tauri::command]
+pub fn preferences_schema(kind: String) -> Result<Value, String> {
+    let (schema, _) = schema_and_check(&kind)?;
+    serde_json::to_value(schema).map_err(|e| e.to_string())
+}
+
+// List of problems with `json` as a `kind` document; empty means it loads cleanly
+#[tauri::command]
+pub fn validate_preferences(json: String, kind: Option<String>) -> Result<Vec<String>, String> {
+    let (schema, check) = schema_and_check(kind.as_deref().unwrap_or("user"))?;
+    let value: Value = match serde_json::from_str(&json) {
+        Ok(v) => v,
+        Err(e) => return Ok(vec![format!("Not valid JSON: {}", e)]),
+    };
+    let Some(obj) = value.as_object() else {
+        return Ok(vec!["Expected a JSON object at the top level".to_string()]);
+    };
+
+    let known = known_keys(&schema);
+    let mut problems = Vec::new();
+    let mut rest = serde_json::Map::new();
+    for (key, val) in obj {
+        if known.iter().any(|k| k == key) {
+            rest.insert(key.clone(), val.clone());
+            continue;
+        }
+        match suggest(key, &known) {
+            Some(k) => problems.push(format!("Unknown key '{}', did you mean '{}'?", key, k)),
+            None => problems.push(format!("Unknown key '{}' (known keys: {})", key, known.join(", "))),
+        }
+    }
+    // Type errors are checked on the known keys only so they aren't masked by the unknown ones
+    if let Err(e) = check(Value::Object(rest)) {
+        problems.push(format!("Invalid value: {}", e));
+    }
+    Ok(problems)
+}
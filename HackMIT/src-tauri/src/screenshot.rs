@@ -1,14 +1,71 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::time::{Duration, Instant};
-use tauri::Emitter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Emitter, Manager};
 use device_query::DeviceQuery;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::{broadcast, Mutex};
+use crate::claude::ContextHint;
+use crate::config::{AppConfig, CaptureMode, TagSmoothing};
+use crate::control::{generation_slot, unless_stopped, PipelineControl};
+use crate::frames::{Frame, FrameSource};
+use crate::metrics::{timed, Metrics, STAGE_CAPTURE, STAGE_CLASSIFY, STAGE_CLAUDE, STAGE_HASH, STAGE_SUNO};
 
-// Capture screenshot using "screenshots" crate
-fn capture_active_display(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
+// Push a metrics:update roughly once a minute at the 5s cadence
+const METRICS_EMIT_EVERY_TICKS: u64 = 12;
+const ROTATE_SCREENSHOTS_EVERY_TICKS: u64 = 12;
+
+const PERMISSION_HELP: &str = "Screen Recording permission is required. Open System Settings > Privacy & Security > Screen Recording, enable this app, then restart it.";
+// Luma variance below this is treated as a flat (likely permission-blocked) frame
+const BLANK_VARIANCE: f64 = 1.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionStatus {
+    pub granted: bool,
+    pub message: Option<String>,
+}
+
+impl PermissionStatus {
+    fn needed() -> Self {
+        Self { granted: false, message: Some(PERMISSION_HELP.to_string()) }
+    }
+}
+
+// macOS returns an all-black (or single-color) frame instead of failing when permission is missing
+fn looks_blank(rgba: &[u8]) -> bool {
+    let pixels = rgba.len() / 4;
+    if pixels == 0 { return true; }
+    let step = (pixels / 4096).max(1);
+    let (mut n, mut sum, mut sum_sq) = (0f64, 0f64, 0f64);
+    for px in rgba.chunks_exact(4).step_by(step) {
+        let luma = 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64;
+        n += 1.0;
+        sum += luma;
+        sum_sq += luma * luma;
+    }
+    let mean = sum / n;
+    sum_sq / n - mean * mean < BLANK_VARIANCE
+}
+
+fn is_permission_error(err: &anyhow::Error) -> bool {
+    let msg = format!("{:#}", err).to_lowercase();
+    ["permission", "not authorized", "denied", "tcc"].iter().any(|k| msg.contains(k))
+}
+
+#[tauri::command]
+pub async fn check_screen_permission() -> Result<PermissionStatus, String> {
+    match grab_display(CaptureMode::Screen).map(|(img, _)| img) {
+        Ok(img) if cfg!(target_os = "macos") && looks_blank(img.as_raw()) => Ok(PermissionStatus::needed()),
+        Ok(_) => Ok(PermissionStatus { granted: true, message: None }),
+        Err(e) if is_permission_error(&e) => Ok(PermissionStatus::needed()),
+        Err(e) => Err(format!("capture failed: {e}")),
+    }
+}
+
+// Grab the display under the cursor (or the frontmost window) without touching disk; also returns
+// the display's position in Screen::all()
+pub(crate) fn grab_display(mode: CaptureMode) -> Result<(screenshots::image::RgbaImage, Option<usize>)> {
     use screenshots::Screen; // macOS supported
     // Try to pick screen under current mouse cursor; fall back to (0,0)
     let (mx, my) = {
@@ -18,22 +75,191 @@ fn capture_active_display(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
     };
     let screen = Screen::from_point(mx, my).or_else(|_| Screen::from_point(0, 0))
         .context("No screen found to capture")?;
-    let img = screen.capture().context("Failed to capture screen")?;
-    let width = img.width();
-    let height = img.height();
-    let buffer = img.into_raw();
+    let monitor_index = Screen::all().ok().and_then(|all| all.iter().position(|s| s.display_info.id == screen.display_info.id));
+    let mut img = screen.capture().context("Failed to capture screen")?;
+    if mode == CaptureMode::Window {
+        match window_crop_rect(&screen.display_info, img.width(), img.height()) {
+            Some((x, y, w, h)) => img = screenshots::image::imageops::crop_imm(&img, x, y, w, h).to_image(),
+            None => println!("Frontmost window bounds unavailable, capturing full screen"),
+        }
+    }
+    Ok((img, monitor_index))
+}
+
+// Where a frame came from, kept with timeline entries for debugging multi-monitor setups
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureMeta {
+    pub monitor_index: Option<usize>, // position in Screen::all()
+    pub resolution: (u32, u32),       // captured pixels, after any window crop
+    pub frontmost_app: Option<String>,
+}
+
+// Payload of context:settling, emitted each tick while the first classification waits
+#[derive(Debug, Clone, Serialize)]
+pub struct Settling {
+    pub stable_ticks: u32, // consecutive ticks with the frame distance at or below the threshold
+    pub needed: u32,       // config settle_ticks
+    pub distance: Option<u32>, // this tick's frame distance; None on the first frame
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayChanged {
+    pub from: (u32, u32), // previous capture size
+    pub to: (u32, u32),
+}
+
+// Intermediate values of one run_cycle decision, for get_last_decision_debug
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionDebug {
+    pub at_ms: u64,
+    pub trigger: String,              // "timer" or "manual"
+    pub distance: Option<u32>,        // previous frame vs this one; None on the first frame or after a display change
+    pub threshold: u32,               // distance above which a frame counts as a context change
+    pub inflight_distance: Option<u32>, // frame the running classification started from vs this one
+    pub app_changed: bool,            // frontmost app differs from the previous decision's
+    pub tag_same: bool,               // context tag unchanged from the previous decision
+    pub corrected_tag: Option<String>, // tag taken from a saved correction
+    pub floor_active: bool,           // inside the min track interval
+    pub rate_limited: bool,
+    pub focus_locked: bool,
+    pub observing: bool,              // startup grace
+    pub settling: bool,               // first classification waiting for the screen to settle
+    pub held: bool,                   // switch held back by a classification in flight
+    pub blend_held: bool,             // switch dropped to keep the blended track for an alternating pair
+    pub queued: bool,
+    pub action: String,               // the context:decision action
+}
+
+#[derive(Serialize)]
+struct DecisionRecord<'a> {
+    #[serde(flatten)]
+    decision: &'a DecisionEvent,
+    capture: &'a CaptureMeta,
+}
+
+// Take the next frame from `frames` (normally the screen). `png_buf` is reused across ticks so the
+// encoder doesn't reallocate a full-frame PNG every time; the raw frame is moved out, never copied.
+fn capture_active_display(path: &Path, frames: &dyn FrameSource, png_buf: &mut Vec<u8>, prep: Preprocess) -> Result<(u32, u32, Vec<u8>, Option<usize>)> {
+    let Frame { width, height, rgba: buffer, monitor_index } = frames.next_frame()?;
     // Write PNG for debugging/Claude
+    encode_preprocessed_into(png_buf, width, height, &buffer, prep)?;
+    let _ = std::fs::create_dir_all(path.parent().unwrap());
+    // Written under another name and renamed, so readers never see a half-written current.png
+    let part = path.with_extension("png.part");
+    if std::fs::write(&part, &png_buf[..]).is_ok() {
+        let _ = std::fs::rename(&part, path);
+    }
+    Ok((width, height, buffer, monitor_index))
+}
+
+// Optional adjustments to the image Claude sees (config.json grayscale / contrast / max_image_width); off by default
+#[derive(Debug, Clone, Copy)]
+struct Preprocess {
+    grayscale: bool,
+    contrast: f32, // image::imageops::contrast amount, e.g. 20.0; 0 = unchanged
+    max_width: u32, // 0 = native size
+}
+
+impl Preprocess {
+    fn from_config(config: &AppConfig) -> Self {
+        Self {
+            grayscale: config.grayscale,
+            contrast: if config.contrast.is_finite() { config.contrast } else { 0.0 },
+            max_width: config.max_image_width,
+        }
+    }
+}
+
+// Size after scaling `width` x `height` down uniformly to at most `max_width` wide (0 = no cap).
+// Only ever shrinks, so the height stays within whatever bound it already met.
+fn clamp_to_width(width: u32, height: u32, max_width: u32) -> (u32, u32) {
+    if max_width == 0 || width <= max_width { return (width, height); }
+    let scaled = (height as u64 * max_width as u64 + width as u64 / 2) / width as u64;
+    (max_width, (scaled as u32).max(1))
+}
+
+// The saved PNG gets the preprocessing; `rgba` itself (what gets hashed) is left as captured so the
+// change detection doesn't shift when the knobs are turned. Grayscale is written as a 1-channel PNG.
+fn encode_preprocessed_into(out: &mut Vec<u8>, width: u32, height: u32, rgba: &[u8], prep: Preprocess) -> Result<()> {
+    let (w, h) = clamp_to_width(width, height, prep.max_width);
+    if !prep.grayscale && prep.contrast == 0.0 && (w, h) == (width, height) {
+        return encode_png_into(out, width, height, rgba);
+    }
+    let mut img = image::RgbaImage::from_raw(width, height, rgba.to_vec()).context("Frame size doesn't match its buffer")?;
+    if (w, h) != (width, height) {
+        img = image::imageops::resize(&img, w, h, image::imageops::FilterType::Triangle);
+    }
+    if prep.grayscale {
+        let mut gray = image::imageops::grayscale(&img);
+        if prep.contrast != 0.0 { image::imageops::contrast_in_place(&mut gray, prep.contrast); }
+        encode_png_as(out, w, h, gray.as_raw(), png::ColorType::Grayscale)
+    } else {
+        if prep.contrast != 0.0 { image::imageops::contrast_in_place(&mut img, prep.contrast); }
+        encode_png_into(out, w, h, img.as_raw())
+    }
+}
+
+// Encode into `out`, replacing its contents but keeping its capacity
+fn encode_png_into(out: &mut Vec<u8>, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    encode_png_as(out, width, height, rgba, png::ColorType::Rgba)
+}
+
+fn encode_png_as(out: &mut Vec<u8>, width: u32, height: u32, data: &[u8], color: png::ColorType) -> Result<()> {
+    out.clear();
+    let mut encoder = png::Encoder::new(&mut *out, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context("PNG write_header failed")?;
+    writer.write_image_data(data).context("PNG write_image_data failed")?;
+    writer.finish().context("PNG finish failed")?;
+    Ok(())
+}
+
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
     let mut png_bytes = Vec::new();
-    {
-        let mut encoder = png::Encoder::new(&mut png_bytes, width, height);
-        encoder.set_color(png::ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header().context("PNG write_header failed")?;
-        writer.write_image_data(&buffer).context("PNG write_image_data failed")?;
+    encode_png_into(&mut png_bytes, width, height, rgba)?;
+    Ok(png_bytes)
+}
+
+// Longest side of the capture_preview thumbnail
+const PREVIEW_MAX_SIDE: u32 = 640;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturePreview {
+    pub data_url: String, // data:image/png;base64,...
+    pub width: u32,       // thumbnail size
+    pub height: u32,
+    pub source_width: u32, // size of the full capture
+    pub source_height: u32,
+}
+
+// Show the UI what the app sees: capture like the periodic task would (same mode and blocklist),
+// but keep it in memory and never classify or generate
+#[tauri::command]
+pub async fn capture_preview() -> Result<CapturePreview, String> {
+    use base64::Engine as _;
+    let root = crate::claude::project_root().map_err(|e| e.to_string())?;
+    let config = crate::config::load_config(&root);
+    if is_blocked_app(frontmost_app_name().as_deref(), &config.blocklist_apps) {
+        return Err("The frontmost app is blocklisted and is never captured".to_string());
     }
-    let _ = std::fs::create_dir_all(path.parent().unwrap());
-    let _ = std::fs::write(path, &png_bytes);
-    Ok((width, height, buffer))
+    tokio::task::spawn_blocking(move || {
+        let (img, _) = grab_display(config.capture)?;
+        let (source_width, source_height) = img.dimensions();
+        let scale = (PREVIEW_MAX_SIDE as f64 / source_width.max(source_height) as f64).min(1.0);
+        let thumb = screenshots::image::imageops::thumbnail(
+            &img,
+            ((source_width as f64 * scale).round() as u32).max(1),
+            ((source_height as f64 * scale).round() as u32).max(1),
+        );
+        let (width, height) = thumb.dimensions();
+        let png_bytes = encode_png(width, height, thumb.as_raw())?;
+        let data_url = format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png_bytes));
+        Ok(CapturePreview { data_url, width, height, source_width, source_height })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: anyhow::Error| format!("capture failed: {:#}", e))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +267,8 @@ pub struct ContextSummary {
     pub tag: String,           // short label, e.g., "vscode", "browser-google-docs"
     pub details: String,       // brief sentence
     pub app: Option<String>,   // frontmost app name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>, // evidence for the tag, e.g. "visible file tree and code editor"; absent in older saved summaries
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,27 +276,88 @@ pub struct DecisionEvent {
     pub current_context: ContextSummary,
     pub previous_context: Option<ContextSummary>,
     pub is_similar: bool,
-    pub action: String, // "continue" or "switch_with_fade"
-}
-
-async fn summarize_context(image_path: &Path) -> Result<ContextSummary> {
-    // Reuse Claude caller but with a smaller prompt and token budget
-    let prompt = "You are classifying the user's current activity from a screenshot.\nReturn JSON ONLY as:\n{\n  tag: stable kebab-case tag focusing on app/site and activity (e.g., 'vscode-coding', 'chrome-docs', 'terminal-build', 'figma-design'),\n  details: one short sentence\n}\nKeep the tag stable across very similar screenshots.";
-    // Use existing function to call Anthropic with image; then parse JSON
-    let _ = dotenvy::dotenv();
-    let root = crate::claude::project_root().context("Find project root failed")?;
-    let _ = dotenvy::from_filename(root.join(".env"));
-    let api_key = std::env::var("ANTHROPIC_API_KEY").context("ANTHROPIC_API_KEY missing")?;
-    let client = reqwest::Client::new();
-    // Use a faster, smaller Claude call for low latency classification
-    let raw = crate::claude::call_anthropic_quick(&client, &api_key, image_path, prompt)
-        .await
-        .context("Claude classify call failed")?;
-    let maybe = crate::claude::extract_json_block(&raw).unwrap_or(raw);
-    #[derive(Deserialize)]
-    struct Resp { tag: String, details: String }
-    let parsed: Resp = serde_json::from_str(&maybe).context("Parse context summary JSON failed")?;
-    Ok(ContextSummary { tag: parsed.tag, details: parsed.details, app: None })
+    pub action: String, // "continue", "continue_and_queue", "switch_with_fade", "blend", "observe" (startup grace), "stopped" or "generated"
+    pub fade_ms: Option<u64>, // crossfade length, only set for "switch_with_fade"
+    pub chosen_tags: Option<String>, // only set for "generated": what Claude picked for the switch
+    pub chosen_topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>, // what the user asserted through classify_with_hint, for this decision and its "generated" follow-up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blended_context: Option<ContextSummary>, // only set for "blend": the context alternating with current_context
+}
+
+// Sent when a context came from the app name / window title instead of Claude
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalFallback {
+    pub summary: ContextSummary,
+    pub reason: String, // "local_classification" (config) or why Claude failed
+}
+
+// Lowercase kebab-case from free text, e.g. "Google Chrome" -> "google-chrome"
+fn kebab(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Coarse context from the frontmost app (tag from config app_tags, else the app name) and its window
+// title; the bool says whether the app was in app_tags. None when the app can't be detected.
+fn local_context(app_tags: &std::collections::BTreeMap<String, String>) -> Option<(ContextSummary, bool)> {
+    let app = frontmost_app_name()?;
+    let mapped = app_tags.iter().find(|(name, _)| name.eq_ignore_ascii_case(&app)).map(|(_, tag)| tag.clone());
+    let details = match frontmost_window_title() {
+        Some(title) => format!("{} - {}", app, title),
+        None => app.clone(),
+    };
+    let tag = mapped.clone().unwrap_or_else(|| kebab(&app));
+    let reason = if mapped.is_some() { format!("{} is mapped in app_tags", app) } else { format!("frontmost app is {}", app) };
+    Some((ContextSummary { tag, details, app: Some(app), reason: Some(reason) }, mapped.is_some()))
+}
+
+// A request from a locally classified context, announced with context:local_fallback
+fn local_generation(app: &tauri::AppHandle, summary: ContextSummary, reason: String, fe_prefs: Option<crate::claude::FrontendPreferences>) -> Result<crate::claude::GeneratedRequest, String> {
+    let _ = app.emit("context:local_fallback", LocalFallback { summary: summary.clone(), reason });
+    let mut generated = crate::claude::local_request(&summary, fe_prefs).map_err(|e| format!("{:#}", e))?;
+    generated.context = Some(summary);
+    Ok(generated)
+}
+
+// claude:classifying_start / claude:classifying_done payloads, for a spinner with an estimate
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassifyingStart {
+    pub eta_ms: Option<u64>, // rolling average of recent classification calls; None before the first
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassifyingDone {
+    pub elapsed_ms: u64,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+// Bracket a Claude classification with start/done events (done fires on errors too) and feed its
+// duration into the "classify" metrics stage the next ETA comes from
+async fn timed_classification<T>(handle: &tauri::AppHandle, call: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    let metrics = handle.state::<Metrics>();
+    let eta_ms = metrics.average(STAGE_CLASSIFY).map(|d| d.as_millis() as u64);
+    let _ = handle.emit("claude:classifying_start", ClassifyingStart { eta_ms });
+    let started = Instant::now();
+    let result = call.await;
+    let elapsed = started.elapsed();
+    metrics.record(STAGE_CLASSIFY, elapsed);
+    let _ = handle.emit("claude:classifying_done", ClassifyingDone {
+        elapsed_ms: elapsed.as_millis() as u64,
+        ok: result.is_ok(),
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    });
+    result
+}
+
+// "tag (details)" for prompts
+fn describe_context(summary: &ContextSummary) -> String {
+    format!("{} ({})", summary.tag, summary.details)
 }
 
 // Basic tag comparison used for switch decision (no image similarity thresholds)
@@ -76,6 +365,30 @@ fn tags_differ(a: &ContextSummary, b: &ContextSummary) -> bool {
     !a.tag.eq_ignore_ascii_case(&b.tag)
 }
 
+// Cheap context from the frontmost app name, no Claude call
+pub(crate) fn current_app_summary() -> ContextSummary {
+    let app_name = frontmost_app_name();
+    ContextSummary {
+        tag: app_name.clone().unwrap_or_else(|| "unknown".to_string()),
+        details: format!("App: {:?}", app_name),
+        reason: app_name.as_ref().map(|a| format!("frontmost app is {}", a)),
+        app: app_name,
+    }
+}
+
+// Case-insensitive substring match against the frontmost app name. Only as good as that detection:
+// when the name is unknown (non-macOS, osascript denied) nothing is blocked.
+fn is_blocked_app(app: Option<&str>, blocklist: &[String]) -> bool {
+    let Some(app) = app else { return false };
+    let app = app.to_lowercase();
+    blocklist.iter().any(|b| !b.trim().is_empty() && app.contains(&b.trim().to_lowercase()))
+}
+
+// With a non-empty allowlist, only matching apps are in scope; an unreadable app name matches nothing
+fn is_out_of_scope(app: Option<&str>, allowlist: &[String]) -> bool {
+    allowlist.iter().any(|a| !a.trim().is_empty()) && !is_blocked_app(app, allowlist)
+}
+
 fn frontmost_app_name() -> Option<String> {
     // macOS: use AppleScript via osascript (may require Accessibility permission)
     #[cfg(target_os = "macos")]
@@ -92,16 +405,90 @@ fn frontmost_app_name() -> Option<String> {
     None
 }
 
+// Title of the frontmost app's front window (macOS, needs Accessibility permission)
+fn frontmost_window_title() -> Option<String> {
+    #[cfg(target_os = "macos")]
+    {
+        use std::process::Command;
+        let script = r#"tell application "System Events" to get name of front window of (first process whose frontmost is true)"#;
+        if let Ok(out) = Command::new("osascript").arg("-e").arg(script).output() {
+            if out.status.success() {
+                let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+                if !s.is_empty() { return Some(s); }
+            }
+        }
+    }
+    None
+}
+
+// Bounds (x, y, width, height) in global display points of the frontmost app's front window
+fn frontmost_window_bounds() -> Option<(f64, f64, f64, f64)> {
+    // macOS: CoreGraphics window list is ordered front to back, so the first normal-layer
+    // window owned by the frontmost app is the one the user is looking at
+    #[cfg(target_os = "macos")]
+    {
+        use core_foundation::base::{CFType, TCFType};
+        use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+        use core_foundation::number::CFNumber;
+        use core_foundation::string::CFString;
+        use core_graphics::geometry::CGRect;
+        use core_graphics::window::{copy_window_info, kCGNullWindowID, kCGWindowListExcludeDesktopElements, kCGWindowListOptionOnScreenOnly};
+
+        let owner = frontmost_app_name()?;
+        let windows = copy_window_info(kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements, kCGNullWindowID)?;
+        let layer_key = CFString::from_static_string("kCGWindowLayer");
+        let owner_key = CFString::from_static_string("kCGWindowOwnerName");
+        let bounds_key = CFString::from_static_string("kCGWindowBounds");
+        for item in windows.iter() {
+            let dict: CFDictionary<CFString, CFType> = unsafe { CFDictionary::wrap_under_get_rule(*item as CFDictionaryRef) };
+            let layer = dict.find(&layer_key).and_then(|v| v.downcast::<CFNumber>()).and_then(|n| n.to_i64());
+            if layer != Some(0) { continue; }
+            let name = dict.find(&owner_key).and_then(|v| v.downcast::<CFString>()).map(|s| s.to_string());
+            if name.as_deref() != Some(owner.as_str()) { continue; }
+            let bounds = dict.find(&bounds_key).and_then(|v| v.downcast::<CFDictionary>())?;
+            let rect = CGRect::from_dict_representation(&bounds)?;
+            return Some((rect.origin.x, rect.origin.y, rect.size.width, rect.size.height));
+        }
+    }
+    None
+}
+
+// Map the frontmost window onto the captured buffer of `display`, in pixels, clamped to the image
+fn window_crop_rect(display: &screenshots::display_info::DisplayInfo, img_w: u32, img_h: u32) -> Option<(u32, u32, u32, u32)> {
+    let (wx, wy, ww, wh) = frontmost_window_bounds()?;
+    if display.width == 0 || display.height == 0 { return None; }
+    // Captures are in physical pixels while window bounds are in points
+    let sx = img_w as f64 / display.width as f64;
+    let sy = img_h as f64 / display.height as f64;
+    let x0 = ((wx - display.x as f64) * sx).max(0.0) as u32;
+    let y0 = ((wy - display.y as f64) * sy).max(0.0) as u32;
+    let x1 = (((wx + ww - display.x as f64) * sx).max(0.0) as u32).min(img_w);
+    let y1 = (((wy + wh - display.y as f64) * sy).max(0.0) as u32).min(img_h);
+    if x1 <= x0 || y1 <= y0 { return None; }
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
 // Fast image hash for context change detection
 #[derive(Clone)]
 struct ImageSig {
     hash: img_hash::ImageHash,
 }
 
-fn compute_sig(width: u32, height: u32, rgba: &[u8]) -> Result<ImageSig> {
+// Cheap exact-match check ahead of the perceptual hash; only ever compared for equality
+fn frame_checksum(width: u32, height: u32, rgba: &[u8]) -> u64 {
+    use std::hash::Hasher;
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    h.write_u32(width);
+    h.write_u32(height);
+    h.write(rgba);
+    h.finish()
+}
+
+// Takes the frame by value: img_hash wants its own image type, and the frame isn't needed afterwards
+fn compute_sig(width: u32, height: u32, rgba: Vec<u8>) -> Result<ImageSig> {
     use img_hash::{HasherConfig, HashAlg};
     use img_hash::image::{ImageBuffer, Rgba, DynamicImage};
-    let buf: ImageBuffer<Rgba<u8>, _> = ImageBuffer::from_vec(width, height, rgba.to_vec())
+    let buf: ImageBuffer<Rgba<u8>, _> = ImageBuffer::from_vec(width, height, rgba)
         .ok_or_else(|| anyhow::anyhow!("Failed to build image buffer"))?;
     let dynimg = DynamicImage::ImageRgba8(buf);
     let hasher = HasherConfig::new().hash_alg(HashAlg::Mean).hash_size(8, 8).to_hasher();
@@ -109,132 +496,1147 @@ fn compute_sig(width: u32, height: u32, rgba: &[u8]) -> Result<ImageSig> {
     Ok(ImageSig { hash })
 }
 
+// Calculate maximum possible distance for 8x8 hash (64 bits)
+// Each bit can differ, so max distance is 64
+pub(crate) const MAX_HASH_DISTANCE: u32 = 64;
+const CHANGE_THRESHOLD_PERCENT: f32 = 0.10; // 10%
+pub(crate) const THRESHOLD_DISTANCE: u32 = (MAX_HASH_DISTANCE as f32 * CHANGE_THRESHOLD_PERCENT) as u32;
+
+// Bigger visual change => longer crossfade: 0.5x the configured fade just over the threshold, up to 2x
+// for a completely different screen. Unknown distance (first frame, manual trigger) uses the base.
+fn fade_for_distance(base_ms: u64, distance: u32) -> u64 {
+    if distance > MAX_HASH_DISTANCE { return base_ms; }
+    let span = (MAX_HASH_DISTANCE - THRESHOLD_DISTANCE) as f64;
+    let t = (distance.saturating_sub(THRESHOLD_DISTANCE) as f64 / span).clamp(0.0, 1.0);
+    (base_ms as f64 * (0.5 + 1.5 * t)).round() as u64
+}
+
 fn sig_distance(a: &ImageSig, b: &ImageSig) -> u32 {
     a.hash.dist(&b.hash)
 }
 
-pub fn start_periodic_task(app_handle: tauri::AppHandle) {
-    #[derive(Clone)]
-    struct SharedState {
-        prev_sig: Option<ImageSig>,
-        last_switch: Option<Instant>,
+#[derive(Clone)]
+struct SharedState {
+    prev_sig: Option<ImageSig>,
+    last_switch: Option<Instant>,
+    prev_summary: Option<ContextSummary>,
+    queued_switch: bool, // a big change arrived inside the min track interval
+    infer_in_flight: bool, // a Claude classification is running
+    inflight_sig: Option<ImageSig>, // frame that classification started from
+    pending_sig: Option<ImageSig>, // newest frame seen while it was running
+    corrections: Vec<(ImageSig, String)>, // user-corrected tags by frame, from corrections.json
+    track_secs: Option<f32>, // length of the track now playing, when the clip reported one
+    prev_dims: Option<(u32, u32)>, // capture size prev_sig was hashed from
+    last_debug: Option<DecisionDebug>, // numbers behind the latest periodic/manual decision
+    generations_waiting: usize, // periodic generations queued behind the running one
+    coalesced_generation: bool, // a switch arrived with the queue full; regenerate once a slot frees up
+    track_context: Option<ContextSummary>, // context when the playing track was generated; restored if Claude refuses
+    settled: bool, // a classification has started since launch (or reset_state)
+    prev_checksum: Option<u64>, // frame_checksum of the previous frame
+    recent_tags: std::collections::VecDeque<String>, // last tag_smoothing_window raw tags, newest last
+    stable_ticks: u32, // consecutive quiet ticks while not yet settled
+    context_changes: std::collections::VecDeque<(ContextSummary, Instant)>, // last few tag changes, oldest first
+    blend: Option<Blend>, // the pair the user is alternating between, while that lasts
+}
+
+// Two contexts the user flips between quickly; one track is made for both and then held
+#[derive(Debug, Clone)]
+struct Blend {
+    a: ContextSummary,
+    b: ContextSummary,
+    generated: bool, // the blended track has been started; further switches within the pair are dropped
+}
+
+impl Blend {
+    fn contains(&self, tag: &str) -> bool { self.a.tag == tag || self.b.tag == tag }
+}
+
+// On-disk copy of the parts of SharedState that should survive a restart (suno-config/state.json)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PersistedState {
+    last_switch_at_ms: Option<u64>, // unix millis
+    prev_summary: Option<ContextSummary>,
+    prev_sig: Option<String>, // base64 perceptual hash
+}
+
+fn state_path(root: &Path) -> PathBuf { root.join("suno-config").join("state.json") }
+
+fn load_persisted_state(root: &Path) -> PersistedState {
+    std::fs::read_to_string(state_path(root))
+        .ok()
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+fn save_persisted_state(root: &Path, persisted: &PersistedState) -> Result<()> {
+    let p = state_path(root);
+    if let Some(dir) = p.parent() { let _ = std::fs::create_dir_all(dir); }
+    std::fs::write(&p, serde_json::to_string_pretty(persisted)?).context("write state.json")?;
+    Ok(())
+}
+
+// A user's fix for a misclassified frame, kept in suno-config/corrections.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Correction {
+    tag: String,
+    sig: String, // base64 perceptual hash of the corrected frame
+    at_ms: u64,
+}
+
+// Frames at most this far from a corrected one get the corrected tag
+const CORRECTION_MATCH_DISTANCE: u32 = 3;
+
+fn corrections_path(root: &Path) -> PathBuf { root.join("suno-config").join("corrections.json") }
+
+fn load_corrections(root: &Path) -> Vec<Correction> {
+    std::fs::read_to_string(corrections_path(root))
+        .ok()
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+fn append_correction(root: &Path, correction: Correction) -> Result<()> {
+    let mut all = load_corrections(root);
+    all.push(correction);
+    let p = corrections_path(root);
+    if let Some(dir) = p.parent() { let _ = std::fs::create_dir_all(dir); }
+    std::fs::write(&p, serde_json::to_string_pretty(&all)?).context("write corrections.json")?;
+    Ok(())
+}
+
+fn unix_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+impl SharedState {
+    fn from_persisted(persisted: PersistedState) -> Self {
+        // Rebase the wall-clock timestamp onto the monotonic clock so the cooldown keeps counting
+        let last_switch = persisted.last_switch_at_ms.and_then(|ms| {
+            let ago = SystemTime::now().duration_since(UNIX_EPOCH + Duration::from_millis(ms)).unwrap_or_default();
+            Instant::now().checked_sub(ago)
+        });
+        let prev_sig = persisted
+            .prev_sig
+            .as_deref()
+            .and_then(|b| img_hash::ImageHash::from_base64(b).ok())
+            .map(|hash| ImageSig { hash });
+        Self { prev_sig, last_switch, prev_summary: persisted.prev_summary, queued_switch: false, infer_in_flight: false, inflight_sig: None, pending_sig: None, corrections: Vec::new(), track_secs: None, prev_dims: None, last_debug: None, generations_waiting: 0, coalesced_generation: false, track_context: None, settled: false, prev_checksum: None, recent_tags: std::collections::VecDeque::new(), stable_ticks: 0, context_changes: std::collections::VecDeque::new(), blend: None }
+    }
+
+    // Hash distance from the previous frame, plus the size change when there was one. Hashes of
+    // differently sized captures aren't comparable (monitor plugged in, resolution changed): start a
+    // fresh baseline at distance 0 instead of reading the difference as a context change.
+    fn baseline_distance(&mut self, sig: &ImageSig, dims: (u32, u32)) -> (u32, Option<DisplayChanged>) {
+        let display_change = self.prev_dims.filter(|d| *d != dims).map(|from| DisplayChanged { from, to: dims });
+        self.prev_dims = Some(dims);
+        if display_change.is_some() {
+            self.prev_sig = None;
+            self.pending_sig = None;
+        }
+        let distance = match self.prev_sig.as_ref() {
+            Some(prev) => sig_distance(sig, prev),
+            None if display_change.is_some() => 0,
+            None => 999, // First screenshot = big change
+        };
+        (distance, display_change)
+    }
+
+    // Floor between switches: a share of the playing track's length (capped), never below min_track_interval_secs
+    fn min_track_interval(&self, config: &AppConfig) -> Duration {
+        let floor = Duration::from_secs(config.min_track_interval_secs);
+        let Some(track_secs) = self.track_secs.filter(|s| s.is_finite() && *s > 0.0) else { return floor };
+        let scaled = (track_secs as f64 * config.track_interval_fraction).min(config.max_track_interval_secs as f64);
+        floor.max(Duration::from_secs_f64(scaled.max(0.0)))
+    }
+
+    // Remember this tick's raw tag and return the one decisions should use: the latest, or with
+    // TagSmoothing::Majority the most frequent of the window (ties go to the most recent)
+    fn smoothed_tag(&mut self, tag: String, config: &AppConfig) -> String {
+        let window = config.tag_smoothing_window.max(1);
+        self.recent_tags.push_back(tag);
+        while self.recent_tags.len() > window { self.recent_tags.pop_front(); }
+        let latest = self.recent_tags.back().cloned().unwrap_or_default();
+        if config.tag_smoothing != TagSmoothing::Majority { return latest; }
+        let count = |t: &String| self.recent_tags.iter().filter(|x| *x == t).count();
+        // max_by_key keeps the last of equal maxima, and the window runs oldest to newest
+        self.recent_tags.iter().max_by_key(|t| count(t)).cloned().unwrap_or(latest)
+    }
+
+    // Record `summary` if its tag differs from the last one seen. Returns (current, other) when the last
+    // four changes alternate A,B,A,B within `window`.
+    fn note_context_change(&mut self, summary: &ContextSummary, window: Duration) -> Option<(ContextSummary, ContextSummary)> {
+        if self.context_changes.back().map_or(true, |(c, _)| c.tag != summary.tag) {
+            self.context_changes.push_back((summary.clone(), Instant::now()));
+            while self.context_changes.len() > 4 { self.context_changes.pop_front(); }
+        }
+        let changes: Vec<&(ContextSummary, Instant)> = self.context_changes.iter().collect();
+        let [(first, at), (second, _), (third, _), (fourth, _)] = changes.as_slice() else { return None };
+        // Neighbours always differ, so two matching pairs means exactly two tags
+        let alternating = first.tag == third.tag && second.tag == fourth.tag && at.elapsed() <= window;
+        alternating.then(|| (summary.clone(), third.clone()))
+    }
+
+    // Most recent correction whose frame is near-identical to `sig`
+    fn corrected_tag(&self, sig: &ImageSig) -> Option<String> {
+        self.corrections
+            .iter()
+            .rev()
+            .find(|(s, _)| sig_distance(s, sig) <= CORRECTION_MATCH_DISTANCE)
+            .map(|(_, tag)| tag.clone())
     }
 
+    fn to_persisted(&self) -> PersistedState {
+        PersistedState {
+            last_switch_at_ms: self.last_switch.map(|t| unix_ms(SystemTime::now() - t.elapsed())),
+            prev_summary: self.prev_summary.clone(),
+            prev_sig: self.prev_sig.as_ref().map(|s| s.hash.to_base64()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    Timer,
+    Manual, // hotkey press: always generates, but still records the frame for the timer's de-dup
+}
+
+// Everything a capture -> decide -> generate cycle needs; shared by the timer and the hotkey
+pub struct Pipeline {
+    root: PathBuf,
+    shot_path: PathBuf,
+    config: AppConfig,
+    state: Mutex<SharedState>,
+    launched_at: Instant,
+    permission_warned: AtomicBool,
+    png_buf: std::sync::Mutex<Vec<u8>>, // reused PNG encode buffer for the capture loop
+    interval_ms: AtomicU64, // periodic capture cadence; the loop picks up changes on its next tick
+    frames: Box<dyn FrameSource>, // the screen, or a replayed directory (HACKMIT_FRAME_DIR)
+    decisions: broadcast::Sender<DecisionEvent>, // every decision; context:decision is one subscriber
+}
+
+// Decisions buffered per subscriber before a slow one starts missing them
+const DECISION_CHANNEL_CAPACITY: usize = 64;
+
+impl Pipeline {
+    fn new(root: PathBuf, config: AppConfig, frames: Box<dyn FrameSource>, state: SharedState) -> Self {
+        Self {
+            shot_path: root.join("temp").join("current.png"),
+            interval_ms: AtomicU64::new(crate::config::capture_interval(config.capture_interval_secs).as_millis() as u64),
+            frames,
+            config,
+            state: Mutex::new(state),
+            launched_at: Instant::now(),
+            permission_warned: AtomicBool::new(false),
+            png_buf: std::sync::Mutex::new(Vec::new()),
+            decisions: broadcast::channel(DECISION_CHANNEL_CAPACITY).0,
+            root,
+        }
+    }
+
+    // The decision stream for in-process code (tests, a headless runner) that has no webview to listen on
+    pub fn subscribe_decisions(&self) -> broadcast::Receiver<DecisionEvent> {
+        self.decisions.subscribe()
+    }
+
+    // Sending with no subscribers is fine; the decision is simply not observed
+    pub(crate) fn publish_decision(&self, evt: DecisionEvent) {
+        let _ = self.decisions.send(evt);
+    }
+
+    // Current periodic capture cadence, including set_capture_interval changes
+    pub(crate) fn capture_interval_secs(&self) -> f64 {
+        Duration::from_millis(self.interval_ms.load(Ordering::SeqCst)).as_secs_f64()
+    }
+}
+
+pub fn start_periodic_task(app_handle: tauri::AppHandle) {
     let root = crate::claude::project_root().unwrap_or(std::env::current_dir().unwrap());
-    let shot_path = root.join("temp").join("current.png");
-    let state = Arc::new(Mutex::new(SharedState {
-        prev_sig: None,
-        last_switch: None,
-    }));
+    let manual_only = crate::config::load_hotkeys(&root).manual_only;
+    let config = crate::config::load_config(&root);
+    let frames = crate::frames::from_env(config.capture);
+    let mut state = SharedState::from_persisted(load_persisted_state(&root));
+    state.corrections = load_corrections(&root)
+        .into_iter()
+        .filter_map(|c| img_hash::ImageHash::from_base64(&c.sig).ok().map(|hash| (ImageSig { hash }, c.tag)))
+        .collect();
+    app_handle.manage(Pipeline::new(root, config, frames, state));
+    forward_decisions(&app_handle);
+    if manual_only {
+        println!("Manual-only capture: waiting for the hotkey");
+        return;
+    }
     let app = app_handle.clone();
 
     tauri::async_runtime::spawn(async move {
-        // Screenshot every 5 seconds
-        let mut ticker = tokio::time::interval(Duration::from_secs(5));
+        let pipeline = app.state::<Pipeline>();
+        let mut period = Duration::from_millis(pipeline.interval_ms.load(Ordering::SeqCst));
+        let mut ticker = tokio::time::interval(period);
+        let mut tick_count: u64 = 0;
         loop {
             ticker.tick().await;
+            // set_capture_interval changed the cadence: restart the ticker at the new period
+            let wanted = Duration::from_millis(pipeline.interval_ms.load(Ordering::SeqCst));
+            if wanted != period {
+                period = wanted;
+                ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            }
+            if app.state::<PipelineControl>().is_stopped() { continue; }
+
+            tick_count += 1;
+            if tick_count.is_multiple_of(METRICS_EMIT_EVERY_TICKS) {
+                let _ = app.emit("metrics:update", app.state::<Metrics>().snapshot());
+            }
+            if tick_count.is_multiple_of(ROTATE_SCREENSHOTS_EVERY_TICKS) {
+                rotate_screenshots(&app).await;
+            }
+            run_cycle(&app, Trigger::Timer, None).await;
+            emit_heartbeat(&app).await;
+        }
+    });
+}
+
+// Images the screenshot readers (find_latest_screenshot) pick from
+fn is_screenshot_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "png" | "jpg" | "jpeg"))
+}
+
+// Delete all but the newest `keep` images in `temp_dir`, never touching `protect`
+fn prune_screenshots(temp_dir: &Path, keep: usize, protect: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(temp_dir) else { return 0 };
+    let mut shots: Vec<(PathBuf, SystemTime)> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| is_screenshot_file(p))
+        .map(|p| {
+            let mtime = std::fs::metadata(&p).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+            (p, mtime)
+        })
+        .collect();
+    shots.sort_by(|a, b| b.1.cmp(&a.1));
+    shots
+        .into_iter()
+        .skip(keep)
+        .filter(|(p, _)| p != protect)
+        .filter(|(p, _)| std::fs::remove_file(p).is_ok())
+        .count()
+}
+
+// Keeps temp/ bounded. Skipped while anything may be reading a screenshot: a classification is
+// armed or the generation slot is taken (every Claude call that reads temp/ holds it)
+async fn rotate_screenshots(app: &tauri::AppHandle) {
+    let pipeline = app.state::<Pipeline>();
+    let control = app.state::<PipelineControl>();
+    let st = pipeline.state.lock().await; // held until done so no classification can start meanwhile
+    if st.infer_in_flight { return; }
+    let Some(_slot) = control.try_generation_slot() else { return };
+    let temp_dir = pipeline.shot_path.parent().map(Path::to_path_buf).unwrap_or_else(|| pipeline.root.join("temp"));
+    let keep = pipeline.config.keep_screenshots.max(1);
+    let protect = pipeline.shot_path.clone();
+    let removed = tokio::task::spawn_blocking(move || prune_screenshots(&temp_dir, keep, &protect))
+        .await
+        .unwrap_or(0);
+    if removed > 0 {
+        println!("Removed {} old screenshot(s) from temp/", removed);
+    }
+}
+
+// Relay the decision channel to the webview as context:decision; subscribed before any decision is made
+fn forward_decisions(app: &tauri::AppHandle) {
+    let mut decisions = app.state::<Pipeline>().subscribe_decisions();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match decisions.recv().await {
+                Ok(evt) => { let _ = app.emit("context:decision", &evt); }
+                Err(broadcast::error::RecvError::Lagged(missed)) => println!("context:decision relay fell behind, {} decision(s) dropped", missed),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
 
-            // Capture screenshot
-            let (w, h, rgba) = match capture_active_display(&shot_path) {
-                Ok(v) => v,
-                Err(e) => { 
-                    let _ = app.emit("screenshot:error", format!("capture failed: {e}")); 
-                    continue; 
+// Change the periodic capture cadence at runtime (e.g. a power-saving mode); returns the interval in use
+#[tauri::command]
+pub async fn set_capture_interval(pipeline: tauri::State<'_, Pipeline>, secs: f64) -> Result<f64, String> {
+    let interval = crate::config::capture_interval(secs);
+    pipeline.interval_ms.store(interval.as_millis() as u64, Ordering::SeqCst);
+    Ok(interval.as_secs_f64())
+}
+
+// Sent every tick, whatever was decided, so the UI can show it's alive and holding the track
+#[derive(Debug, Clone, Serialize)]
+pub struct Heartbeat {
+    pub tag: Option<String>,
+    pub secs_since_switch: Option<u64>,
+    pub inference_armed: bool, // a big change right now would start a new track
+    pub focus_lock_remaining_secs: Option<u64>,
+    pub generation_queue: usize, // generations waiting behind the running one
+}
+
+async fn emit_heartbeat(app: &tauri::AppHandle) {
+    let pipeline = app.state::<Pipeline>();
+    let config = &pipeline.config;
+    let focus_remaining = app.state::<PipelineControl>().focus_remaining();
+    let beat = {
+        let st = pipeline.state.lock().await;
+        let since_switch = st.last_switch.map(|t| t.elapsed());
+        let floor_active = since_switch.is_some_and(|d| d < st.min_track_interval(config));
+        let observing = pipeline.launched_at.elapsed() < Duration::from_secs(config.startup_grace_secs);
+        Heartbeat {
+            tag: st.prev_summary.as_ref().map(|s| s.tag.clone()),
+            secs_since_switch: since_switch.map(|d| d.as_secs()),
+            inference_armed: !st.infer_in_flight && !floor_active && !observing && focus_remaining.is_none(),
+            focus_lock_remaining_secs: focus_remaining.map(|d| d.as_secs()),
+            generation_queue: st.generations_waiting,
+        }
+    };
+    let _ = app.emit("context:heartbeat", &beat);
+}
+
+// One on-demand cycle, bypassing the timer and the change gates
+pub(crate) fn trigger_manual_cycle(app: &tauri::AppHandle) {
+    if app.state::<PipelineControl>().is_stopped() { return; }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        run_cycle(&app, Trigger::Manual, None).await;
+    });
+}
+
+// Longest classify_with_hint text that goes into the prompt
+const MAX_HINT_CHARS: usize = 200;
+
+// A manual cycle steered by what the UI knows better than the screenshot (e.g. "I'm focusing now").
+// The hint goes to Claude as user-asserted ground truth and is logged with the decision.
+#[tauri::command]
+pub async fn classify_with_hint(app: tauri::AppHandle, control: tauri::State<'_, PipelineControl>, hint: String) -> Result<(), String> {
+    let hint = hint.trim().to_string();
+    if hint.is_empty() { return Err("Hint must not be empty".to_string()); }
+    let len = hint.chars().count();
+    if len > MAX_HINT_CHARS {
+        return Err(format!("Hint is {} characters, the limit is {}", len, MAX_HINT_CHARS));
+    }
+    if control.is_stopped() { return Err("Music is stopped; resume first".to_string()); }
+    run_cycle(&app, Trigger::Manual, Some(hint)).await;
+    Ok(())
+}
+
+async fn run_cycle(app: &tauri::AppHandle, trigger: Trigger, hint: Option<String>) {
+    let pipeline = app.state::<Pipeline>();
+    let metrics = app.state::<Metrics>();
+    let config = &pipeline.config;
+
+    // Privacy: blocklisted apps are never captured, let alone sent to Claude
+    let mut summary = current_app_summary();
+    if is_blocked_app(summary.app.as_deref(), &config.blocklist_apps) {
+        let _ = app.emit("context:blocked", &summary);
+        return;
+    }
+    // Allowlist mode: timer ticks outside the listed apps stay silent; the hotkey still works
+    if trigger == Trigger::Timer && is_out_of_scope(summary.app.as_deref(), &config.allowlist_apps) {
+        let _ = app.emit("context:out_of_scope", &summary);
+        return;
+    }
+
+    // Capture screenshot
+    let started = Instant::now();
+    let captured = {
+        let mut png_buf = pipeline.png_buf.lock().unwrap_or_else(|e| e.into_inner());
+        capture_active_display(&pipeline.shot_path, pipeline.frames.as_ref(), &mut png_buf, Preprocess::from_config(config))
+    };
+    metrics.record(STAGE_CAPTURE, started.elapsed());
+    let (w, h, rgba, monitor_index) = match captured {
+        Ok(v) => v,
+        Err(e) if is_permission_error(&e) => {
+            let _ = app.emit("screenshot:permission_needed", PermissionStatus::needed());
+            return;
+        }
+        Err(e) => {
+            let _ = app.emit("screenshot:error", format!("capture failed: {e}"));
+            return;
+        }
+    };
+    if cfg!(target_os = "macos") && looks_blank(&rgba) {
+        // Warn once per blank streak; never classify a black frame
+        if !pipeline.permission_warned.swap(true, Ordering::SeqCst) {
+            let _ = app.emit("screenshot:permission_needed", PermissionStatus::needed());
+        }
+        return;
+    }
+    pipeline.permission_warned.store(false, Ordering::SeqCst);
+
+    // A byte-identical frame (static screen) reuses the previous hash; on a timer tick with nothing
+    // waiting on the clock (settling, a queued switch) the rest of the tick is skipped too
+    let checksum = frame_checksum(w, h, &rgba);
+    let reused_sig = {
+        let st = pipeline.state.lock().await;
+        let identical = st.prev_checksum == Some(checksum);
+        if identical && trigger == Trigger::Timer && st.settled && !st.queued_switch {
+            return;
+        }
+        if identical { st.prev_sig.clone() } else { None }
+    };
+
+    // Compute image hash
+    let started = Instant::now();
+    let hashed = match reused_sig {
+        Some(sig) => Ok(sig),
+        None => compute_sig(w, h, rgba),
+    };
+    metrics.record(STAGE_HASH, started.elapsed());
+    let current_sig = match hashed {
+        Ok(s) => s,
+        Err(e) => {
+            let _ = app.emit("screenshot:error", format!("hash failed: {e}"));
+            return;
+        }
+    };
+
+    // Check for context change
+    let mut should_switch;
+    let mut focus_locked = false;
+    let observing;
+    let queued;
+    let previous_context;
+    let snapshot;
+    let distance;
+    let corrected;
+    let display_change;
+    let mut floor_active = false;
+    let mut rate_limited = false;
+    let mut settling = None;
+    let held;
+    let mut blend_held = false;
+    let mut blending = None;
+    let inflight_distance;
+    let app_changed;
+    let tag_same;
+    {
+        let mut st = pipeline.state.lock().await;
+        // The user told us what this screen is before; trust that over the app name
+        corrected = st.corrected_tag(&current_sig);
+        summary.tag = st.smoothed_tag(summary.tag.clone(), config);
+        if let Some(tag) = &corrected {
+            summary.tag = tag.clone();
+            summary.reason = Some("user correction for this screen".to_string());
+        }
+        (distance, display_change) = st.baseline_distance(&current_sig, (w, h));
+
+        should_switch = distance > THRESHOLD_DISTANCE;
+        println!("Hash distance: {} (max: {}, threshold: {}), should_switch: {}",
+            distance, MAX_HASH_DISTANCE, THRESHOLD_DISTANCE, should_switch);
+
+        if trigger == Trigger::Manual {
+            // The user asked for a track: skip the interval floor, cooldown and grace period
+            should_switch = true;
+            observing = false;
+            match &hint {
+                Some(h) => println!("Manual trigger with hint {:?}: generating", h),
+                None => println!("Manual trigger: generating"),
+            }
+        } else {
+            // The first classification after launch waits until the screen has stopped changing for
+            // settle_ticks ticks (windows still being arranged), then runs even without a big change
+            if !st.settled {
+                st.stable_ticks = if distance <= THRESHOLD_DISTANCE { st.stable_ticks + 1 } else { 0 };
+                should_switch = st.stable_ticks >= config.settle_ticks;
+                if !should_switch {
+                    println!("Waiting for the screen to settle ({}/{} quiet ticks)", st.stable_ticks, config.settle_ticks);
+                    settling = Some(Settling {
+                        stable_ticks: st.stable_ticks,
+                        needed: config.settle_ticks,
+                        distance: (distance != 999).then_some(distance),
+                    });
                 }
-            };
-
-            // Compute image hash
-            let current_sig = match compute_sig(w, h, &rgba) { 
-                Ok(s) => s, 
-                Err(e) => { 
-                    let _ = app.emit("screenshot:error", format!("hash failed: {e}")); 
-                    continue; 
-                } 
-            };
-
-            // Check for context change
-            let mut should_switch;
-            {
-                let mut st = state.lock().await;
-                let distance = match st.prev_sig.as_ref() {
-                    Some(prev) => sig_distance(&current_sig, prev),
-                    None => 999, // First screenshot = big change
-                };
+            }
 
-                // Calculate maximum possible distance for 8x8 hash (64 bits)
-                // Each bit can differ, so max distance is 64
-                const MAX_HASH_DISTANCE: u32 = 64;
-                const CHANGE_THRESHOLD_PERCENT: f32 = 0.10; // 10%
-                const THRESHOLD_DISTANCE: u32 = (MAX_HASH_DISTANCE as f32 * CHANGE_THRESHOLD_PERCENT) as u32;
-                
-                should_switch = distance > THRESHOLD_DISTANCE;
-                println!("Hash distance: {} (max: {}, threshold: {}), should_switch: {}", 
-                    distance, MAX_HASH_DISTANCE, THRESHOLD_DISTANCE, should_switch);
-                
-                // Rate limiting: don't switch more than once every 3 seconds
-                if should_switch {
-                    if let Some(last) = st.last_switch {
-                        if last.elapsed() < Duration::from_secs(3) {
-                            should_switch = false;
-                            println!("Rate limited: too soon since last switch");
-                        }
-                    }
+            // Rapid A,B,A,B alternation (docs next to code): one track made for both, which is then
+            // kept while the user stays within the pair. A third context, or a whole window spent
+            // in one of them, ends the blend.
+            if config.blend_window_secs > 0 {
+                let window = Duration::from_secs(config.blend_window_secs);
+                let pair = st.note_context_change(&summary, window);
+                let settled_in_one = st.context_changes.back().is_some_and(|(_, at)| at.elapsed() > window);
+                if st.blend.as_ref().is_some_and(|b| !b.contains(&summary.tag) || (b.generated && settled_in_one)) {
+                    println!("Alternation ended: back to normal switching");
+                    st.blend = None;
+                }
+                if let Some((current, other)) = pair.filter(|_| st.blend.is_none()) {
+                    println!("Alternating between {} and {}: blending", current.tag, other.tag);
+                    st.blend = Some(Blend { a: current, b: other, generated: false });
+                    // Queued as well, so the blend still runs if a gate below holds it this tick
+                    should_switch = true;
+                    st.queued_switch = true;
+                }
+                if st.blend.as_ref().is_some_and(|b| b.generated) && (should_switch || st.queued_switch) {
+                    should_switch = false;
+                    st.queued_switch = false;
+                    blend_held = true;
+                    println!("Keeping the blended track");
                 }
+            }
+
+            // Hard floor between tracks, independent of the cooldown below: hold big changes
+            // and fire them once the floor has elapsed
+            let min_track_interval = st.min_track_interval(config);
+            floor_active = st.last_switch.is_some_and(|t| t.elapsed() < min_track_interval);
+            if floor_active && should_switch {
+                should_switch = false;
+                st.queued_switch = true;
+                println!("Within min track interval: queueing switch");
+            } else if !floor_active && st.queued_switch {
+                should_switch = true;
+                println!("Min track interval elapsed: running queued switch");
+            }
 
-                if should_switch {
-                    st.last_switch = Some(Instant::now());
+            // Rate limiting: don't switch more than once every 3 seconds
+            if should_switch {
+                if let Some(last) = st.last_switch {
+                    if last.elapsed() < Duration::from_secs(3) {
+                        should_switch = false;
+                        rate_limited = true;
+                        println!("Rate limited: too soon since last switch");
+                    }
                 }
-                st.prev_sig = Some(current_sig);
-            }
-
-            // Emit context decision immediately
-            let app_name = frontmost_app_name();
-            let summary = ContextSummary {
-                tag: app_name.clone().unwrap_or_else(|| "unknown".to_string()),
-                details: format!("App: {:?}", app_name),
-                app: app_name.clone(),
-            };
-
-            let action = if should_switch { "switch_with_fade" } else { "continue" };
-            let evt = DecisionEvent {
-                current_context: summary.clone(),
-                previous_context: None,
-                is_similar: !should_switch,
-                action: action.to_string(),
-            };
-            let _ = app.emit("context:decision", &evt);
-
-            // If significant change detected, trigger music generation
+            }
+
+            // Focus lock: big changes wait in the queue and fire once the lock is lifted
+            focus_locked = app.state::<PipelineControl>().focus_remaining().is_some();
+            if should_switch && focus_locked {
+                should_switch = false;
+                st.queued_switch = true;
+                println!("Focus lock active: queueing switch");
+            }
+
+            // Startup grace: learn the initial context without committing to a track
+            observing = pipeline.launched_at.elapsed() < Duration::from_secs(config.startup_grace_secs);
+            if observing && should_switch {
+                should_switch = false;
+                println!("Startup grace period: observing only");
+            }
+        }
+
+        // Only one classification at a time; the newest frame waits in the pending slot instead
+        held = st.infer_in_flight && should_switch;
+        inflight_distance = st.inflight_sig.as_ref().map(|s| sig_distance(&current_sig, s));
+        if st.infer_in_flight {
+            st.pending_sig = Some(current_sig.clone());
             if should_switch {
-                println!("Context change detected - triggering music generation");
-                let app_clone = app.clone();
-                tokio::spawn(async move {
-                    // Call Claude to analyze the screenshot and generate Suno request
-                    match crate::claude::regenerate_suno_request_json().await {
-                        Ok(_suno_request) => {
-                            println!("Claude analysis completed, generated Suno request");
-                            
-                            // Call Suno to generate music
-                            match crate::suno::suno_hackmit_generate_and_wait().await {
-                                Ok(audio_url) => {
-                                    println!("Suno generation completed, switching to new audio stream");
-                                    
-                                    // Emit event to frontend to switch to new audio stream
-                                    let _ = app_clone.emit("music:switch", audio_url);
-                                },
-                                Err(e) => {
-                                    println!("Suno generation failed: {}", e);
-                                    let _ = app_clone.emit("music:error", format!("Suno generation failed: {}", e));
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            println!("Claude analysis failed: {}", e);
-                            let _ = app_clone.emit("music:error", format!("Claude analysis failed: {}", e));
+                should_switch = false;
+                println!("Classification in flight: holding newest frame");
+            }
+        }
+
+        if should_switch {
+            if trigger == Trigger::Timer {
+                blending = st.blend.as_mut().filter(|b| !b.generated).map(|b| {
+                    b.generated = true;
+                    let other = if b.a.tag == summary.tag { &b.b } else { &b.a };
+                    other.clone()
+                });
+            }
+            st.settled = true;
+            st.last_switch = Some(Instant::now());
+            st.queued_switch = false;
+            st.infer_in_flight = true;
+            st.inflight_sig = Some(current_sig.clone());
+            st.pending_sig = None;
+        }
+        queued = st.queued_switch || held;
+        let tag_changed = st.prev_summary.as_ref().map(|p| p.tag != summary.tag).unwrap_or(true);
+        tag_same = !tag_changed;
+        app_changed = st.prev_summary.as_ref().is_some_and(|p| p.app != summary.app);
+        st.prev_sig = Some(current_sig);
+        st.prev_checksum = Some(checksum); // kept in step with prev_sig, which is what gets reused
+        previous_context = st.prev_summary.replace(summary.clone());
+        // Only touch disk when something actually moved
+        snapshot = if should_switch || distance > 0 || tag_changed { Some(st.to_persisted()) } else { None };
+    }
+    if let Some(progress) = &settling {
+        let _ = app.emit("context:settling", progress);
+    }
+    if let Some(change) = &display_change {
+        println!("Capture size changed {:?} -> {:?}, resetting hash baseline", change.from, change.to);
+        let _ = app.emit("context:display_changed", change);
+    }
+    if let Some(persisted) = snapshot {
+        let root = pipeline.root.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = save_persisted_state(&root, &persisted) {
+                println!("Failed to persist state: {}", e);
+            }
+        });
+    }
+
+    // Emit context decision immediately
+    let action = if observing || settling.is_some() {
+        "observe"
+    } else if should_switch && blending.is_some() {
+        "blend"
+    } else if should_switch {
+        "switch_with_fade"
+    } else if queued || focus_locked {
+        "continue_and_queue"
+    } else {
+        "continue"
+    };
+    let evt = DecisionEvent {
+        current_context: summary.clone(),
+        previous_context,
+        is_similar: !should_switch,
+        action: action.to_string(),
+        fade_ms: should_switch.then(|| match trigger {
+            Trigger::Manual => config.fade_ms,
+            Trigger::Timer => fade_for_distance(config.fade_ms, distance),
+        }),
+        chosen_tags: None,
+        chosen_topic: None,
+        hint: hint.clone(),
+        blended_context: blending.clone(),
+    };
+    pipeline.publish_decision(evt.clone());
+    pipeline.state.lock().await.last_debug = Some(DecisionDebug {
+        at_ms: unix_ms(SystemTime::now()),
+        trigger: match trigger { Trigger::Timer => "timer", Trigger::Manual => "manual" }.to_string(),
+        distance: (distance != 999 && display_change.is_none()).then_some(distance),
+        threshold: THRESHOLD_DISTANCE,
+        inflight_distance,
+        app_changed,
+        tag_same,
+        corrected_tag: corrected.clone(),
+        floor_active,
+        rate_limited,
+        focus_locked,
+        observing,
+        settling: settling.is_some(),
+        held,
+        blend_held,
+        queued,
+        action: action.to_string(),
+    });
+    // Plain "continue" ticks are noise in the timeline
+    if action != "continue" {
+        let capture = CaptureMeta { monitor_index, resolution: (w, h), frontmost_app: summary.app.clone() };
+        crate::session::record(&pipeline.root, "decision", &DecisionRecord { decision: &evt, capture: &capture });
+    }
+
+    // If significant change detected, trigger music generation
+    if should_switch {
+        println!("Context change detected - triggering music generation");
+        let context_hint = match &blending {
+            Some(other) => Some(ContextHint::Blend(describe_context(&summary), describe_context(other))),
+            None => hint.clone().or(corrected).map(ContextHint::User),
+        };
+        spawn_generation(app.clone(), context_hint, hint);
+    }
+}
+
+// Generated state cleared by reset_state; preferences, config, corrections and .env are kept
+const RESETTABLE_FILES: [&str; 4] = ["state.json", "recent_genres.json", "suno_request.json", "pinned_genre.json"];
+
+// Clean slate for testing: forget the last context, switch timing and genre history as if freshly installed.
+// A generation already running is left to finish.
+#[tauri::command]
+pub async fn reset_state(app: tauri::AppHandle, pipeline: tauri::State<'_, Pipeline>) -> Result<Vec<String>, String> {
+    {
+        let mut st = pipeline.state.lock().await;
+        st.prev_sig = None;
+        st.last_switch = None;
+        st.prev_summary = None;
+        st.queued_switch = false;
+        st.pending_sig = None;
+        st.track_secs = None;
+        st.track_context = None;
+        st.prev_dims = None;
+        st.last_debug = None;
+        st.settled = false;
+        st.prev_checksum = None;
+        st.recent_tags.clear();
+        st.stable_ticks = 0;
+        st.context_changes.clear();
+        st.blend = None;
+    }
+    let dir = pipeline.root.join("suno-config");
+    let mut removed = Vec::new();
+    for name in RESETTABLE_FILES {
+        match std::fs::remove_file(dir.join(name)) {
+            Ok(()) => removed.push(name.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {}: {}", name, e)),
+        }
+    }
+    crate::session::record(&pipeline.root, "reset", &removed);
+    let _ = app.emit("context:reset", &removed);
+    Ok(removed)
+}
+
+// Why the last decision came out the way it did; None until the first capture
+#[tauri::command]
+pub async fn get_last_decision_debug(pipeline: tauri::State<'_, Pipeline>) -> Result<Option<DecisionDebug>, String> {
+    Ok(pipeline.state.lock().await.last_debug.clone())
+}
+
+// Replace the current context's tag, remember it for near-identical frames and regenerate with it
+#[tauri::command]
+pub async fn correct_classification(app: tauri::AppHandle, pipeline: tauri::State<'_, Pipeline>, tag: String) -> Result<ContextSummary, String> {
+    let tag = tag.trim().to_string();
+    if tag.is_empty() { return Err("Tag must not be empty".to_string()); }
+    let (summary, correction, start) = {
+        let mut st = pipeline.state.lock().await;
+        let sig = st.prev_sig.clone().ok_or_else(|| "Nothing has been captured yet".to_string())?;
+        let mut summary = st.prev_summary.clone().unwrap_or_else(current_app_summary);
+        summary.tag = tag.clone();
+        summary.reason = Some("user correction".to_string());
+        st.prev_summary = Some(summary.clone());
+        let correction = Correction { tag: tag.clone(), sig: sig.hash.to_base64(), at_ms: unix_ms(SystemTime::now()) };
+        st.corrections.push((sig.clone(), tag.clone()));
+        // Same single-classification gate as the periodic path
+        let start = !st.infer_in_flight && !app.state::<PipelineControl>().is_stopped();
+        if start {
+            st.last_switch = Some(Instant::now());
+            st.infer_in_flight = true;
+            st.inflight_sig = Some(sig);
+            st.pending_sig = None;
+        }
+        (summary, correction, start)
+    };
+    crate::session::record(&pipeline.root, "correction", &correction);
+    let root = pipeline.root.clone();
+    tokio::task::spawn_blocking(move || append_correction(&root, correction))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to save correction: {:#}", e))?;
+    if start {
+        spawn_generation(app, Some(ContextHint::User(tag)), None);
+    } else {
+        println!("Correction saved; not regenerating while another generation is running or stopped");
+    }
+    Ok(summary)
+}
+
+// After a skipped generation: go back to the context the playing track was made for, and return it
+async fn keep_track_context(pipeline: &Pipeline) -> Option<ContextSummary> {
+    let mut st = pipeline.state.lock().await;
+    if let Some(ctx) = st.track_context.clone() { st.prev_summary = Some(ctx); }
+    st.prev_summary.clone()
+}
+
+// Claude -> Suno for the frame just captured; results and failures are reported as events
+// `context_hint` steers Claude (a correction, a classify_with_hint hint or a blend); `hint` is only
+// the classify_with_hint text, logged with the "generated" decision
+fn spawn_generation(app: tauri::AppHandle, context_hint: Option<ContextHint>, hint: Option<String>) {
+    tokio::spawn(async move {
+        // Use the UI's preferences when it has pushed any, otherwise the no-prefs path
+        let fe_prefs = app.state::<crate::claude::FrontendPrefsState>().0.lock().await.clone();
+        let control = app.state::<PipelineControl>();
+        // Call Claude to analyze the screenshot and generate Suno request
+        let metrics = app.state::<Metrics>();
+        let pipeline = app.state::<Pipeline>();
+        let client = app.state::<reqwest::Client>();
+        // Backpressure: at most generation_queue_depth generations wait behind the running one. Past
+        // that, switches are coalesced into one regeneration (with the latest context) when it's done.
+        let slot = match control.try_generation_slot() {
+            Some(slot) => Ok(slot),
+            None => {
+                let admitted = {
+                    let mut st = pipeline.state.lock().await;
+                    let admitted = st.generations_waiting < pipeline.config.generation_queue_depth;
+                    if admitted { st.generations_waiting += 1; } else { st.coalesced_generation = true; }
+                    admitted
+                };
+                if admitted {
+                    let slot = generation_slot(&app, &control).await;
+                    pipeline.state.lock().await.generations_waiting -= 1;
+                    slot
+                } else {
+                    Err("generation queue full, will regenerate with the latest context when it frees up".to_string())
+                }
+            }
+        };
+        let slot = match slot {
+            Ok(slot) => slot,
+            Err(e) => {
+                println!("Skipping generation: {}", e);
+                let mut st = pipeline.state.lock().await;
+                st.infer_in_flight = false;
+                st.inflight_sig = None;
+                return;
+            }
+        };
+        let mut refusal = None;
+        let mut low_confidence = None;
+        // With local_classification, apps mapped in app_tags skip Claude altogether (unless the user
+        // asserted a context, or this is a blend, which only Claude can write a prompt for)
+        let local_only = if pipeline.config.local_classification && context_hint.is_none() {
+            local_context(&pipeline.config.app_tags).filter(|(_, mapped)| *mapped).map(|(summary, _)| summary)
+        } else {
+            None
+        };
+        let regenerated = match local_only {
+            Some(summary) => {
+                {
+                    let mut st = pipeline.state.lock().await;
+                    st.infer_in_flight = false;
+                    st.inflight_sig = None;
+                }
+                local_generation(&app, summary, "local_classification".to_string(), fe_prefs.clone())
+            }
+            None => loop {
+                // This call is where Claude reads the screenshot, so it's what the classifying events bracket
+                let regenerated = unless_stopped(&control, timed(&metrics, STAGE_CLAUDE, async {
+                    timed_classification(&app, async {
+                        match fe_prefs.clone() {
+                            Some(prefs) => crate::claude::regenerate_suno_request_json_with_prefs(&client, prefs, context_hint.as_ref()).await,
+                            None => crate::claude::regenerate_suno_request_json(&client, context_hint.as_ref()).await,
                         }
+                    })
+                    .await
+                    .map_err(|e| {
+                        refusal = crate::claude::refusal(&e).map(|r| r.message.clone());
+                        low_confidence = crate::claude::low_confidence(&e).cloned();
+                        e.to_string()
+                    })
+                })).await;
+
+                // If the screen moved on while Claude was busy, classify the newest frame instead
+                let mut st = pipeline.state.lock().await;
+                let newer = match (st.pending_sig.take(), st.inflight_sig.as_ref()) {
+                    (Some(pending), Some(started)) if sig_distance(&pending, started) > THRESHOLD_DISTANCE => Some(pending),
+                    _ => None,
+                };
+                match newer {
+                    Some(pending) if regenerated.is_ok() => {
+                        println!("Context changed during classification - re-classifying newest frame");
+                        st.inflight_sig = Some(pending);
                     }
-                });
+                    _ => {
+                        st.infer_in_flight = false;
+                        st.inflight_sig = None;
+                        break regenerated;
+                    }
+                }
+            },
+        };
+        // Claude failed outright (offline, outage): carry on with a request from the app name and window title
+        let regenerated = match regenerated {
+            Err(e) if refusal.is_none() && low_confidence.is_none() && !control.is_stopped() => match local_context(&pipeline.config.app_tags) {
+                Some((summary, _)) => {
+                    println!("Claude analysis failed ({}), using the app name", e);
+                    local_generation(&app, summary, e, fe_prefs.clone())
+                }
+                None => Err(e),
+            },
+            other => other,
+        };
+        match regenerated {
+            Ok(mut generated) => {
+                // The context this track is for: the local one, or the pipeline's with Claude's evidence
+                // for its read of the screen as the reason
+                let context = match generated.context.take() {
+                    Some(local) => local,
+                    None => {
+                        let mut context = pipeline.state.lock().await.prev_summary.clone().unwrap_or_else(current_app_summary);
+                        if let Some(reason) = generated.request.reason.clone() { context.reason = Some(reason); }
+                        context
+                    }
+                };
+                generated.context = Some(context.clone());
+                println!("Claude analysis completed, generated Suno request from {}", generated.screenshot.display());
+                let _ = app.emit("suno:request_ready", &generated);
+                let suno_request = generated.request;
+                // Follow-up on the same stream: the music the switch turned into, sent before Suno is done
+                let outcome = {
+                    let st = pipeline.state.lock().await;
+                    DecisionEvent {
+                        current_context: context.clone(),
+                        previous_context: st.track_context.clone(),
+                        is_similar: false,
+                        action: "generated".to_string(),
+                        fade_ms: None,
+                        chosen_tags: suno_request.tags.clone(),
+                        chosen_topic: suno_request.topic.clone(),
+                        hint: hint.clone(),
+                        blended_context: None,
+                    }
+                };
+                pipeline.publish_decision(outcome);
+
+                // Call Suno to generate music from the request we just built
+                let generated = unless_stopped(&control, timed(&metrics, STAGE_SUNO, crate::suno::hackmit_generate_clip(&client, &suno_request))).await
+                    .and_then(|clip| {
+                        let url = clip.url.ok_or_else(|| "Clip has no audio URL".to_string())?;
+                        Ok((url, clip.duration))
+                    });
+                match generated {
+                    Ok((audio_url, duration)) => {
+                        println!("Suno generation completed, switching to new audio stream");
+                        // Longer tracks hold off the next switch for longer
+                        {
+                            let mut st = pipeline.state.lock().await;
+                            st.track_secs = duration;
+                            st.track_context = Some(context);
+                        }
+                        let _ = app.emit("suno:track_ready", &audio_url);
+
+                        // Emit event to frontend to switch to new audio stream
+                        let _ = app.emit("music:switch", audio_url);
+                    },
+                    Err(e) => {
+                        println!("Suno generation failed: {}", e);
+                        let _ = app.emit("music:error", format!("Suno generation failed: {}", e));
+                    }
+                }
+            },
+            Err(e) => match (refusal, low_confidence) {
+                // Not a broken switch: keep the track that's playing and the context it was made for
+                (Some(message), _) => {
+                    println!("{}", e);
+                    let fallback_context = keep_track_context(&pipeline).await;
+                    let _ = app.emit("claude:refused", crate::claude::ClaudeRefused { message, fallback_context });
+                }
+                // Same for a shaky read of the screen. Change detection then compares against the playing
+                // track's context, so the next clear frame of the new activity still counts as a switch.
+                (None, Some(low)) => {
+                    println!("{}", e);
+                    let fallback_context = keep_track_context(&pipeline).await;
+                    let _ = app.emit("context:low_confidence", crate::claude::LowConfidenceSkipped { confidence: low.confidence, min_confidence: low.min_confidence, fallback_context });
+                }
+                (None, None) => {
+                    println!("Claude analysis failed: {}", e);
+                    let _ = app.emit("music:error", format!("Claude analysis failed: {}", e));
+                }
+            },
+        }
+
+        // Run the coalesced switch, unless a queued generation (which reads the latest screenshot anyway)
+        // or a classification already covers it
+        let rerun = {
+            let mut st = pipeline.state.lock().await;
+            let rerun = st.coalesced_generation && st.generations_waiting == 0 && !st.infer_in_flight && !control.is_stopped();
+            if st.generations_waiting > 0 || rerun { st.coalesced_generation = false; }
+            if rerun {
+                st.last_switch = Some(Instant::now());
+                st.infer_in_flight = true;
+                st.inflight_sig = st.prev_sig.clone();
+                st.pending_sig = None;
             }
+            rerun
+        };
+        drop(slot);
+        if rerun {
+            println!("Running coalesced generation with the latest context");
+            spawn_generation(app.clone(), None, None);
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoFrames;
+
+    impl FrameSource for NoFrames {
+        fn next_frame(&self) -> Result<Frame> { anyhow::bail!("no frames in tests") }
+    }
+
+    fn test_pipeline() -> Pipeline {
+        let state = SharedState::from_persisted(PersistedState::default());
+        Pipeline::new(std::env::temp_dir(), AppConfig::default(), Box::new(NoFrames), state)
+    }
+
+    // Built from JSON so the optional fields stay None
+    fn decision(action: &str) -> DecisionEvent {
+        serde_json::from_value(serde_json::json!({
+            "current_context": { "tag": "vscode-coding", "details": "Editing Rust", "app": null },
+            "is_similar": false,
+            "action": action,
+            "fade_ms": 1500,
+        }))
+        .unwrap()
+    }
+
+    // 16x16 frame, black on one half and white on the other
+    fn half_black_sig(left: bool) -> ImageSig {
+        let mut rgba = Vec::with_capacity(16 * 16 * 4);
+        for _y in 0..16 {
+            for x in 0..16 {
+                let v = if (x < 8) == left { 0 } else { 255 };
+                rgba.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+        compute_sig(16, 16, rgba).unwrap()
+    }
+
+    #[test]
+    fn display_size_change_resets_the_baseline_without_switching() {
+        let mut st = SharedState::from_persisted(PersistedState::default());
+        st.prev_sig = Some(half_black_sig(true));
+        st.pending_sig = Some(half_black_sig(true));
+        st.prev_dims = Some((1920, 1080));
+        let (distance, change) = st.baseline_distance(&half_black_sig(false), (2560, 1440));
+        assert_eq!(distance, 0);
+        assert!(distance <= THRESHOLD_DISTANCE);
+        let change = change.unwrap();
+        assert_eq!((change.from, change.to), ((1920, 1080), (2560, 1440)));
+        assert!(st.prev_sig.is_none());
+        assert!(st.pending_sig.is_none());
+        assert_eq!(st.prev_dims, Some((2560, 1440)));
+    }
+
+    #[test]
+    fn same_display_size_compares_against_the_previous_frame() {
+        let mut st = SharedState::from_persisted(PersistedState::default());
+        assert_eq!(st.baseline_distance(&half_black_sig(true), (1920, 1080)).0, 999);
+        st.prev_sig = Some(half_black_sig(true));
+        let (distance, change) = st.baseline_distance(&half_black_sig(false), (1920, 1080));
+        assert!(change.is_none());
+        assert!(distance > THRESHOLD_DISTANCE);
+        assert!(st.prev_sig.is_some());
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_published_decisions_in_order() {
+        let pipeline = test_pipeline();
+        let mut first = pipeline.subscribe_decisions();
+        let mut second = pipeline.subscribe_decisions();
+        pipeline.publish_decision(decision("switch_with_fade"));
+        pipeline.publish_decision(decision("generated"));
+        for rx in [&mut first, &mut second] {
+            let evt = rx.recv().await.unwrap();
+            assert_eq!(evt.action, "switch_with_fade");
+            assert_eq!(evt.current_context.tag, "vscode-coding");
+            assert_eq!(rx.recv().await.unwrap().action, "generated");
+        }
+    }
+
+    #[test]
+    fn publishing_without_subscribers_is_fine() {
+        let pipeline = test_pipeline();
+        pipeline.publish_decision(decision("continue"));
+        // A late subscriber only sees what's published after it subscribed
+        let mut rx = pipeline.subscribe_decisions();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn clamp_to_width_keeps_the_aspect_ratio() {
+        assert_eq!(clamp_to_width(3840, 2160, 2560), (2560, 1440)); // 16:9
+        assert_eq!(clamp_to_width(3440, 1440, 2560), (2560, 1072)); // 21:9
+        assert_eq!(clamp_to_width(5120, 1440, 2560), (2560, 720)); // 32:9
+        assert_eq!(clamp_to_width(1440, 2560, 1280), (1280, 2276)); // portrait
+    }
+
+    #[test]
+    fn clamp_to_width_leaves_narrow_captures_alone() {
+        assert_eq!(clamp_to_width(1920, 1080, 2560), (1920, 1080));
+        assert_eq!(clamp_to_width(2560, 1440, 2560), (2560, 1440));
+        assert_eq!(clamp_to_width(5120, 1440, 0), (5120, 1440)); // 0 = no cap
+    }
+
+    #[test]
+    fn clamp_to_width_never_reaches_zero_height() {
+        assert_eq!(clamp_to_width(10_000, 1, 100), (100, 1));
+    }
+}
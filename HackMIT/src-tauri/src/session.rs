@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+
+// Timeline of the current run (decisions and generated requests), one JSON object per line
+fn log_path(root: &Path) -> PathBuf { root.join("suno-config").join("session.jsonl") }
+
+// Files from suno-config that describe where the session ended up; .env is never included
+const STATE_FILES: [&str; 4] = ["suno_request.json", "recent_genres.json", "state.json", "pinned_genre.json"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionEntry {
+    at_ms: u64, // unix millis
+    kind: String,
+    data: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    file: String,
+    kind: String,
+    at_ms: Option<u64>, // when the event happened or the file was last modified
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest {
+    exported_at_ms: u64,
+    include_screenshots: bool,
+    timeline: Vec<ManifestEntry>, // one per session.jsonl line, in order
+    files: Vec<ManifestEntry>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn modified_ms(path: &Path) -> Option<u64> {
+    let t = fs::metadata(path).ok()?.modified().ok()?;
+    t.duration_since(UNIX_EPOCH).ok().map(|d| d.as_millis() as u64)
+}
+
+pub(crate) fn start(root: &Path) {
+    let p = log_path(root);
+    if let Some(dir) = p.parent() { let _ = fs::create_dir_all(dir); }
+    if let Err(e) = File::create(&p) {
+        println!("Failed to reset {}: {}", p.display(), e);
+    }
+}
+
+// Best effort: a failed append never interrupts the pipeline
+pub(crate) fn record(root: &Path, kind: &str, data: &impl Serialize) {
+    let entry = SessionEntry {
+        at_ms: now_ms(),
+        kind: kind.to_string(),
+        data: serde_json::to_value(data).unwrap_or(Value::Null),
+    };
+    let Ok(line) = serde_json::to_string(&entry) else { return };
+    let appended = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(root))
+        .and_then(|mut f| writeln!(f, "{}", line));
+    if let Err(e) = appended {
+        println!("Failed to record session {}: {}", kind, e);
+    }
+}
+
+fn write_bundle(root: &Path, dest: &Path, include_screenshots: bool) -> Result<()> {
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut zip = zip::ZipWriter::new(File::create(dest).with_context(|| format!("create {}", dest.display()))?);
+    let mut manifest = Manifest { exported_at_ms: now_ms(), include_screenshots, timeline: vec![], files: vec![] };
+
+    let log = fs::read_to_string(log_path(root)).unwrap_or_default();
+    for line in log.lines() {
+        if let Ok(entry) = serde_json::from_str::<SessionEntry>(line) {
+            manifest.timeline.push(ManifestEntry { file: "session.jsonl".to_string(), kind: entry.kind, at_ms: Some(entry.at_ms) });
+        }
+    }
+    zip.start_file("session.jsonl", options)?;
+    zip.write_all(log.as_bytes())?;
+
+    let mut files: Vec<(String, PathBuf, &str)> = STATE_FILES
+        .iter()
+        .map(|name| (format!("suno-config/{}", name), root.join("suno-config").join(name), "state"))
+        .collect();
+    if include_screenshots {
+        if let Ok(entries) = fs::read_dir(root.join("temp")) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) == Some("png") {
+                    let name = format!("temp/{}", entry.file_name().to_string_lossy());
+                    files.push((name, path, "screenshot"));
+                }
+            }
+        }
+    }
+    for (name, path, kind) in files {
+        let Ok(bytes) = fs::read(&path) else { continue };
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(&bytes)?;
+        manifest.files.push(ManifestEntry { file: name, kind: kind.to_string(), at_ms: modified_ms(&path) });
+    }
+
+    zip.start_file("manifest.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+// Zip the session timeline, the latest request/state files and (unless excluded) the screenshots in temp/
+#[tauri::command]
+pub async fn export_session(dest: String, include_screenshots: Option<bool>) -> Result<String, String> {
+    let root = crate::claude::project_root().map_err(|e| e.to_string())?;
+    let include_screenshots = include_screenshots.unwrap_or(true);
+    let dest_path = PathBuf::from(&dest);
+    tokio::task::spawn_blocking(move || write_bundle(&root, &dest_path, include_screenshots))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Export failed: {:#}", e))?;
+    Ok(dest)
+}
@@ -0,0 +1,130 @@
+use crate::claude::ModelRate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use tauri::Emitter;
+
+// Running cost estimate for this launch. Recorded where the API calls are made (which have no
+// AppHandle), so it's process-wide rather than managed state; attach() gives it one for events
+// and reads the budget and credit settings from config.json and the token prices from claude.json once.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendSummary {
+    pub claude_calls: u64,
+    pub input_tokens: u64,           // from the API's usage, estimated when it's missing
+    pub output_tokens: u64,
+    pub estimated_usd: f64,          // tokens priced with claude.json model_rates
+    pub suno_generations: u64,       // tasks submitted (HackMIT or Suno API)
+    pub suno_credits_used: i64,      // suno_credits_per_generation per submitted task
+    pub suno_credits_remaining: Option<i64>, // last fetched balance minus the credits used since
+    pub session_budget_usd: Option<f64>,
+    pub budget_exceeded: bool,
+}
+
+// The config.json and claude.json values the tracker needs, fixed at attach()
+#[derive(Debug, Clone)]
+struct SpendSettings {
+    budget_usd: Option<f64>,
+    credits_per_generation: i64,
+    model_rates: BTreeMap<String, ModelRate>,
+}
+
+impl SpendSettings {
+    fn load() -> Self {
+        let root = crate::claude::project_root().ok();
+        let config = root.as_deref().map(crate::config::load_config).unwrap_or_default();
+        let claude = root.as_deref().and_then(|r| crate::claude::load_claude_config(r).ok()).unwrap_or_default();
+        Self { budget_usd: config.session_budget_usd, credits_per_generation: config.suno_credits_per_generation, model_rates: claude.model_rates }
+    }
+}
+
+struct SpendTracker {
+    totals: Mutex<SpendSummary>,
+    app: OnceLock<tauri::AppHandle>,
+    settings: OnceLock<SpendSettings>,
+    defaults: OnceLock<SpendSettings>,
+}
+
+static SPEND: SpendTracker = SpendTracker {
+    totals: Mutex::new(SpendSummary {
+        claude_calls: 0,
+        input_tokens: 0,
+        output_tokens: 0,
+        estimated_usd: 0.0,
+        suno_generations: 0,
+        suno_credits_used: 0,
+        suno_credits_remaining: None,
+        session_budget_usd: None,
+        budget_exceeded: false,
+    }),
+    app: OnceLock::new(),
+    settings: OnceLock::new(),
+    defaults: OnceLock::new(),
+};
+
+// Config defaults until attach() has run
+fn settings() -> &'static SpendSettings {
+    SPEND.settings.get().unwrap_or_else(|| {
+        SPEND.defaults.get_or_init(|| {
+            let defaults = crate::config::AppConfig::default();
+            SpendSettings {
+                budget_usd: defaults.session_budget_usd,
+                credits_per_generation: defaults.suno_credits_per_generation,
+                model_rates: crate::claude::ClaudeConfig::default().model_rates,
+            }
+        })
+    })
+}
+
+pub(crate) fn attach(app: tauri::AppHandle) {
+    let _ = SPEND.settings.set(SpendSettings::load());
+    let _ = SPEND.app.set(app);
+}
+
+// Models missing from model_rates count as free
+fn estimate_usd(rates: &BTreeMap<String, ModelRate>, model: &str, input_tokens: u64, output_tokens: u64) -> f64 {
+    rates.get(model).map_or(0.0, |r| (input_tokens as f64 * r.input_per_mtok + output_tokens as f64 * r.output_per_mtok) / 1_000_000.0)
+}
+
+pub(crate) fn record_claude(model: &str, input_tokens: u64, output_tokens: u64) {
+    let settings = settings();
+    let budget = settings.budget_usd;
+    let usd = estimate_usd(&settings.model_rates, model, input_tokens, output_tokens);
+    let crossed = {
+        let mut totals = SPEND.totals.lock().unwrap_or_else(|e| e.into_inner());
+        totals.claude_calls += 1;
+        totals.input_tokens += input_tokens;
+        totals.output_tokens += output_tokens;
+        totals.estimated_usd += usd;
+        totals.session_budget_usd = budget;
+        // Warn once per launch, when the estimate first goes over
+        let crossed = !totals.budget_exceeded && budget.is_some_and(|b| totals.estimated_usd > b);
+        if crossed { totals.budget_exceeded = true; }
+        crossed.then(|| totals.clone())
+    };
+    if let Some(summary) = crossed {
+        println!("Estimated spend ${:.2} is over the session budget of ${:.2}", summary.estimated_usd, budget.unwrap_or_default());
+        if let Some(app) = SPEND.app.get() {
+            let _ = app.emit("spend:budget_exceeded", &summary);
+        }
+    }
+}
+
+pub(crate) fn record_suno_generation() {
+    let credits = settings().credits_per_generation;
+    let mut totals = SPEND.totals.lock().unwrap_or_else(|e| e.into_inner());
+    totals.suno_generations += 1;
+    totals.suno_credits_used += credits;
+    if let Some(remaining) = totals.suno_credits_remaining.as_mut() { *remaining -= credits; }
+}
+
+// A fresh balance from the Suno API replaces the running estimate
+pub(crate) fn set_suno_balance(credits: i64) {
+    SPEND.totals.lock().unwrap_or_else(|e| e.into_inner()).suno_credits_remaining = Some(credits);
+}
+
+#[tauri::command]
+pub async fn get_spend_summary() -> Result<SpendSummary, String> {
+    let mut summary = SPEND.totals.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    summary.session_budget_usd = settings().budget_usd;
+    Ok(summary)
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
 use std::path::PathBuf;
+use crate::control::{generation_slot, unless_stopped, PipelineControl};
+use crate::metrics::{timed, Metrics, STAGE_CLAUDE, STAGE_SUNO};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, schemars::JsonSchema)]
 pub struct GenerateRequest {
     pub prompt: Option<String>,
     pub style: Option<String>,
@@ -25,6 +27,23 @@ pub struct GenerateRequest {
     pub callback_url: String,
 }
 
+// Continue an existing clip; with defaultParamFlag the prompt/style/title below replace the original's
+#[derive(Debug, Serialize, Clone)]
+pub struct ExtendRequest {
+    #[serde(rename = "defaultParamFlag")]
+    pub default_param_flag: bool,
+    #[serde(rename = "audioId")]
+    pub audio_id: String,
+    pub prompt: Option<String>,
+    pub style: Option<String>,
+    pub title: Option<String>,
+    #[serde(rename = "continueAt", skip_serializing_if = "Option::is_none")]
+    pub continue_at: Option<f32>, // seconds into the clip; Suno picks the end when unset
+    pub model: String,
+    #[serde(rename = "callBackUrl")]
+    pub callback_url: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct GenerateResponse {
     pub code: i32,
@@ -38,11 +57,24 @@ pub struct GenerateData {
     pub task_id: String,
 }
 
-const SUNO_API_URL: &str = "https://api.sunoapi.org/api/v1/generate";
-const SUNO_STATUS_URL: &str = "https://api.sunoapi.org/api/v1/generate/record-info";
-const SUNO_CREDITS_URL: &str = "https://api.sunoapi.org/api/v1/get-credits";
-const HACKMIT_GENERATE_URL: &str = "https://studio-api.prod.suno.com/api/v2/external/hackmit/generate";
-const HACKMIT_CLIPS_URL: &str = "https://studio-api.prod.suno.com/api/v2/external/hackmit/clips";
+// Hosts can be overridden with SUNO_BASE_URL / HACKMIT_BASE_URL (local mocks, relays)
+const SUNO_DEFAULT_BASE: &str = "https://api.sunoapi.org";
+const HACKMIT_DEFAULT_BASE: &str = "https://studio-api.prod.suno.com";
+const SUNO_API_PATH: &str = "/api/v1/generate";
+const SUNO_STATUS_PATH: &str = "/api/v1/generate/record-info";
+const SUNO_CREDITS_PATH: &str = "/api/v1/get-credits";
+const SUNO_EXTEND_PATH: &str = "/api/v1/generate/extend";
+const HACKMIT_GENERATE_PATH: &str = "/api/v2/external/hackmit/generate";
+const HACKMIT_CLIPS_PATH: &str = "/api/v2/external/hackmit/clips";
+
+pub(crate) fn suno_base_url() -> String {
+    crate::config::base_url("SUNO_BASE_URL", SUNO_DEFAULT_BASE)
+}
+
+pub(crate) fn hackmit_base_url() -> String {
+    crate::config::base_url("HACKMIT_BASE_URL", HACKMIT_DEFAULT_BASE)
+}
+
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TrackInfo {
@@ -56,6 +88,34 @@ pub struct TrackInfo {
     pub stream_audio_url: Option<String>,
 }
 
+// Which URL a caller wants: the stream starts playing sooner, the audio file is the complete download
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlPreference {
+    #[default]
+    Stream,
+    Download,
+}
+
+// What the frontend needs to show a track: where to play it plus title/length for the progress bar
+#[derive(Debug, Serialize, Clone)]
+pub struct TrackResult {
+    pub url: String,
+    pub title: Option<String>,
+    pub duration: Option<f32>, // seconds
+    pub tags: Option<String>,
+}
+
+impl TrackResult {
+    fn from_track(track: TrackInfo, prefer: UrlPreference) -> Option<Self> {
+        let url = match prefer {
+            UrlPreference::Stream => track.stream_audio_url.or(track.audio_url),
+            UrlPreference::Download => track.audio_url.or(track.stream_audio_url),
+        }?;
+        Some(Self { url, title: track.title, duration: track.duration, tags: track.tags })
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct StatusInnerResponse {
     pub data: Option<Vec<TrackInfo>>,
@@ -77,7 +137,7 @@ pub struct StatusResponse {
 }
 
 #[tauri::command]
-pub async fn suno_generate_from_file() -> Result<String, String> {
+pub async fn suno_generate_from_file(client: tauri::State<'_, reqwest::Client>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>) -> Result<String, String> {
     // Load .env once (it's ok to call multiple times; it’s idempotent)
     let _ = dotenvy::dotenv();
 
@@ -86,59 +146,47 @@ pub async fn suno_generate_from_file() -> Result<String, String> {
     // Also try loading env from suno-config/.env explicitly
     let _ = dotenvy::from_filename(base_dir.join("suno-config").join(".env"));
 
-    let api_key = std::env::var("SUNO_API_KEY").map_err(|_| {
-        "SUNO_API_KEY not set. Put it in suno-config/.env as SUNO_API_KEY=...".to_string()
+    let api_key = crate::keys::api_key("SUNO_API_KEY").ok_or_else(|| {
+        "SUNO_API_KEY not set. Store it with set_api_key or put it in suno-config/.env as SUNO_API_KEY=...".to_string()
     })?;
     let req_path = base_dir.join("suno-config").join("request.json");
     let req_text = std::fs::read_to_string(&req_path)
         .map_err(|e| format!("Failed reading {}: {}", req_path.display(), e))?;
-    let payload: GenerateRequest = serde_json::from_str(&req_text)
+    let mut payload: GenerateRequest = serde_json::from_str(&req_text)
         .map_err(|e| format!("Invalid JSON in request.json: {}", e))?;
+    let fe = fe_prefs.0.lock().await.clone();
+    payload.model = resolve_model(fe.as_ref(), payload.model)?;
+    payload.vocal_gender = resolve_vocal_gender(fe.as_ref(), &payload)?;
 
-    let client = reqwest::Client::new();
-    let res = client
-        .post(SUNO_API_URL)
-        .bearer_auth(api_key)
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("HTTP error: {}", e))?;
-
-    let status = res.status();
-    let text = res.text().await.map_err(|e| e.to_string())?;
+    counted(submit_generate(&client, &suno_base_url(), &api_key, &payload).await)
+}
 
-    if !status.is_success() {
-        return Err(format!("Suno API error ({}): {}", status, text));
-    }
+const SUNO_API_NAME: &str = "Suno API";
+const HACKMIT_API_NAME: &str = "HackMIT Suno endpoint";
 
-    let parsed: GenerateResponse = serde_json::from_str(&text)
-        .map_err(|e| format!("Failed to parse response: {}. Raw: {}", e, text))?;
-
-    if parsed.code != 200 {
-        return Err(format!("Suno API returned code {}: {}", parsed.code, parsed.msg));
+// Error for a non-2xx response. 401/403 mean the key itself is bad, so say which one to fix instead of dumping the body.
+fn http_error(what: &str, api: &str, status: reqwest::StatusCode, body: &str) -> String {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            format!("{} rejected the token ({}): check SUNO_API_KEY (set_api_key or .env) is valid and not expired", api, status)
+        }
+        _ => format!("{} ({}): {}", what, status, body),
     }
-
-    let task_id = parsed
-        .data
-        .ok_or_else(|| "Missing data in response".to_string())?
-        .task_id;
-
-    Ok(task_id)
 }
 
 async fn load_api_key() -> Result<String, String> {
     // Load root .env (project root with package.json)
     let _ = dotenvy::dotenv();
     if let Ok(root) = crate_root() { let _ = dotenvy::from_filename(root.join(".env")); }
-    std::env::var("SUNO_API_KEY").map_err(|_| {
-        "SUNO_API_KEY not set. Put it in project root .env as SUNO_API_KEY=...".to_string()
+    crate::keys::api_key("SUNO_API_KEY").ok_or_else(|| {
+        "SUNO_API_KEY not set. Store it with set_api_key or put it in project root .env as SUNO_API_KEY=...".to_string()
     })
 }
 
+// The main API request; suno_request.json is the HackMIT-shaped one and doesn't parse as a GenerateRequest
 async fn load_request() -> Result<GenerateRequest, String> {
-    let path = find_suno_config_file("suno_request.json")
-        .or_else(|| find_suno_config_file("request.json"))
-        .ok_or_else(|| "Could not find suno-config/suno_request.json".to_string())?;
+    let path = find_suno_config_file("request.json")
+        .ok_or_else(|| "Could not find suno-config/request.json".to_string())?;
     let req_text = std::fs::read_to_string(&path)
         .map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
     serde_json::from_str(&req_text).map_err(|e| format!("Invalid JSON in request.json: {}", e))
@@ -159,14 +207,83 @@ fn find_suno_config_file(name: &str) -> Option<PathBuf> {
     None
 }
 
-fn crate_root() -> Result<PathBuf, String> {
-    let start = std::env::current_dir().map_err(|e| e.to_string())?;
-    for dir in start.ancestors() {
-        if dir.join("package.json").exists() {
-            return Ok(dir.to_path_buf());
-        }
+// Suno API model identifiers accepted as overrides
+pub(crate) const SUNO_MODELS: [&str; 5] = ["V3_5", "V4", "V4_5", "V4_5PLUS", "V5"];
+
+// Model for a main-API request: frontend suno_model > config.json suno_model > what request.json says
+fn resolve_model(fe_prefs: Option<&crate::claude::FrontendPreferences>, from_request: String) -> Result<String, String> {
+    let configured = crate_root().ok().and_then(|root| crate::config::load_config(&root).suno_model);
+    let Some(model) = fe_prefs.and_then(|fp| fp.suno_model.clone()).or(configured) else { return Ok(from_request) };
+    let normalized = model.trim().to_ascii_uppercase();
+    if SUNO_MODELS.contains(&normalized.as_str()) {
+        Ok(normalized)
+    } else {
+        Err(format!("Unknown Suno model '{}' (allowed: {})", model, SUNO_MODELS.join(", ")))
+    }
+}
+
+// Suno's vocalGender from the UI's vocals_gender: "male" -> "m", "female" -> "f", nothing for "none"
+// or instrumental tracks (where request.json's own value is dropped too)
+fn resolve_vocal_gender(fe_prefs: Option<&crate::claude::FrontendPreferences>, payload: &GenerateRequest) -> Result<Option<String>, String> {
+    let instrumental = payload.instrumental || fe_prefs.and_then(|fp| fp.instrumental) == Some(true);
+    if instrumental { return Ok(None); }
+    let Some(wanted) = fe_prefs.and_then(|fp| fp.vocals_gender.as_deref()) else { return Ok(payload.vocal_gender.clone()) };
+    match wanted.trim().to_ascii_lowercase().as_str() {
+        "male" | "m" => Ok(Some("m".to_string())),
+        "female" | "f" => Ok(Some("f".to_string())),
+        "none" | "" => Ok(None),
+        _ => Err(format!("Unknown vocals_gender '{}' (allowed: male, female, none)", wanted)),
+    }
+}
+
+// Suno API custom-mode limit on titles
+const MAX_TITLE_CHARS: usize = 80;
+
+// A short title from Claude's topic: its first clause, cut at a word boundary within MAX_TITLE_CHARS
+fn title_from_topic(topic: &str) -> String {
+    let clause = topic.split(['.', ',', ';', ':', '\n']).next().unwrap_or(topic).trim();
+    if clause.chars().count() <= MAX_TITLE_CHARS { return clause.to_string(); }
+    let mut title = String::new();
+    for word in clause.split_whitespace() {
+        if title.chars().count() + word.chars().count() + 1 > MAX_TITLE_CHARS { break; }
+        if !title.is_empty() { title.push(' '); }
+        title.push_str(word);
+    }
+    if title.is_empty() { clause.chars().take(MAX_TITLE_CHARS).collect() } else { title }
+}
+
+fn check_weight(name: &str, value: Option<f32>) -> Result<Option<f32>, String> {
+    match value {
+        Some(v) if !(0.0..=1.0).contains(&v) => Err(format!("{} must be between 0 and 1, got {}", name, v)),
+        _ => Ok(value),
     }
-    Err("Could not locate project root".to_string())
+}
+
+// Claude's HackMIT-shaped request on top of request.json: topic -> title, tags -> style (topic when
+// there are none), lyrics -> prompt, negative_tags -> negativeTags; model, vocal gender and weights
+// from the frontend preferences where set. Always custom mode, since style and lyrics are given.
+pub(crate) fn generate_request_from(base: GenerateRequest, req: &crate::claude::HackmitGenerateReq, fe_prefs: Option<&crate::claude::FrontendPreferences>) -> Result<GenerateRequest, String> {
+    let filled = |v: &Option<String>| v.clone().filter(|s| !s.trim().is_empty());
+    let instrumental = req.make_instrumental.unwrap_or(base.instrumental);
+    let mut payload = GenerateRequest {
+        prompt: if instrumental { None } else { filled(&req.prompt) },
+        style: filled(&req.tags).or_else(|| filled(&req.topic)).or(base.style.clone()),
+        title: filled(&req.topic).map(|t| title_from_topic(&t)).or(base.title.clone()),
+        custom_mode: true,
+        instrumental,
+        negative_tags: filled(&req.negative_tags).or(base.negative_tags.clone()),
+        style_weight: check_weight("style_weight", fe_prefs.and_then(|fp| fp.style_weight).or(base.style_weight))?,
+        weirdness_constraint: check_weight("weirdness_constraint", fe_prefs.and_then(|fp| fp.weirdness_constraint).or(base.weirdness_constraint))?,
+        audio_weight: check_weight("audio_weight", fe_prefs.and_then(|fp| fp.audio_weight).or(base.audio_weight))?,
+        ..base
+    };
+    payload.model = resolve_model(fe_prefs, std::mem::take(&mut payload.model))?;
+    payload.vocal_gender = resolve_vocal_gender(fe_prefs, &payload)?;
+    Ok(payload)
+}
+
+fn crate_root() -> Result<PathBuf, String> {
+    crate::claude::project_root().map_err(|e| e.to_string())
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -182,28 +299,71 @@ struct CreditsResponse {
 }
 
 #[tauri::command]
-pub async fn suno_get_credits() -> Result<i64, String> {
+pub async fn suno_get_credits(client: tauri::State<'_, reqwest::Client>) -> Result<i64, String> {
     let api_key = load_api_key().await?;
-    let client = reqwest::Client::new();
-    let res = client
-        .get(SUNO_CREDITS_URL)
-        .bearer_auth(&api_key)
-        .send()
-        .await
-        .map_err(|e| format!("HTTP error: {}", e))?;
-    let status = res.status();
-    let text = res.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() {
-        return Err(format!("Credits API error ({}): {}", status, text));
-    }
-    let parsed: CreditsResponse = serde_json::from_str(&text)
-        .map_err(|e| format!("Failed to parse credits response: {}. Raw: {}", e, text))?;
-    if parsed.code != 200 {
-        return Err(format!("Suno API returned code {}: {}", parsed.code, parsed.msg));
-    }
-    Ok(parsed.data.and_then(|d| d.credits).unwrap_or(0))
+    let credits = fetch_credits(&client, &suno_base_url(), &api_key).await?;
+    crate::spend::set_suno_balance(credits);
+    Ok(credits)
+}
+
+// Setup-screen summary: balance plus the models that can be chosen
+#[derive(Debug, Serialize, Clone)]
+pub struct AccountInfo {
+    pub credits: Option<i64>, // None when the balance couldn't be fetched
+    pub credits_error: Option<String>,
+    pub credits_per_generation: i64, // config.json estimate, the same for every model
+    pub models: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    pub affordable: Option<bool>, // enough credits for one generation; None when the balance is unknown
+}
+
+// Credits and models in one call; a failed balance fetch still returns the models. The Suno API has
+// no models endpoint, so the list is SUNO_MODELS.
+#[tauri::command]
+pub async fn suno_account_info(client: tauri::State<'_, reqwest::Client>) -> Result<AccountInfo, String> {
+    let credits = match load_api_key().await {
+        Ok(api_key) => fetch_credits(&client, &suno_base_url(), &api_key).await,
+        Err(e) => Err(e),
+    };
+    if let Ok(balance) = &credits { crate::spend::set_suno_balance(*balance); }
+    let per_generation = crate_root()
+        .map(|root| crate::config::load_config(&root))
+        .unwrap_or_default()
+        .suno_credits_per_generation;
+    let balance = credits.as_ref().ok().copied();
+    Ok(AccountInfo {
+        credits: balance,
+        credits_error: credits.err(),
+        credits_per_generation: per_generation,
+        models: SUNO_MODELS
+            .iter()
+            .map(|id| ModelInfo { id: id.to_string(), affordable: balance.map(|b| b >= per_generation) })
+            .collect(),
+    })
+}
+
+// Check a HackMIT request id once (no polling), e.g. to recover a track that finished after a timeout
+#[tauri::command]
+pub async fn suno_hackmit_check(client: tauri::State<'_, reqwest::Client>, id: String) -> Result<Vec<ClipResult>, String> {
+    let api_key = load_api_key().await?;
+    let clips = hackmit_fetch_clips(&client, &hackmit_base_url(), &api_key, id.trim()).await?;
+    Ok(clips.into_iter().map(ClipResult::from_clip).collect())
+}
+
+// Same for a main Suno API task id: its current status and any tracks so far
+#[tauri::command]
+pub async fn suno_check_status(client: tauri::State<'_, reqwest::Client>, task_id: String) -> Result<StatusData, String> {
+    let api_key = load_api_key().await?;
+    let status = get_status(&client, &suno_base_url(), &api_key, task_id.trim()).await?;
+    status.data.ok_or_else(|| format!("Suno API returned code {} with no task data: {}", status.code, status.msg))
 }
 
+// The body the HackMIT generate endpoint accepts. Claude's request also carries negative_tags, which only
+// the main Suno API takes (as negativeTags), so it is dropped here.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct HackmitGenerateReq {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -218,13 +378,25 @@ struct HackmitGenerateReq {
     cover_clip_id: Option<String>,
 }
 
+impl From<&crate::claude::HackmitGenerateReq> for HackmitGenerateReq {
+    fn from(req: &crate::claude::HackmitGenerateReq) -> Self {
+        Self {
+            topic: req.topic.clone(),
+            tags: req.tags.clone(),
+            prompt: req.prompt.clone(),
+            make_instrumental: req.make_instrumental,
+            cover_clip_id: req.cover_clip_id.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct HackmitGenerateResp {
     id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-struct HackmitClip {
+pub(crate) struct HackmitClip {
     id: String,
     request_id: Option<String>,
     created_at: Option<String>,
@@ -234,6 +406,33 @@ struct HackmitClip {
     audio_url: Option<String>,
 }
 
+// One take of a HackMIT generation, labelled so the UI can A/B the variations
+#[derive(Debug, Serialize, Clone)]
+pub struct ClipResult {
+    pub id: String,
+    pub url: Option<String>, // None while this take is still rendering
+    pub status: Option<String>, // as reported by the clips endpoint, e.g. "streaming" or "complete"
+    pub title: Option<String>,
+    pub tags: Option<String>,
+    pub duration: Option<f32>, // seconds
+}
+
+impl ClipResult {
+    // Tags and duration live in the untyped metadata; missing or oddly shaped fields just stay None
+    fn from_clip(clip: HackmitClip) -> Self {
+        let meta = clip.metadata.as_ref();
+        let field = |key: &str| meta.and_then(|m| m.get(key));
+        Self {
+            id: clip.id,
+            url: clip.audio_url,
+            status: clip.status,
+            title: clip.title.or_else(|| field("title").and_then(|v| v.as_str()).map(str::to_string)),
+            tags: field("tags").and_then(|v| v.as_str()).map(str::to_string),
+            duration: field("duration").and_then(|v| v.as_f64()).map(|d| d as f32),
+        }
+    }
+}
+
 async fn load_hackmit_request() -> Result<HackmitGenerateReq, String> {
     let path = find_suno_config_file("hackmit-request.json")
         .ok_or_else(|| "Could not find suno-config/hackmit-request.json".to_string())?;
@@ -242,193 +441,310 @@ async fn load_hackmit_request() -> Result<HackmitGenerateReq, String> {
     serde_json::from_str(&txt).map_err(|e| format!("Invalid JSON in hackmit-request.json: {}", e))
 }
 
-#[tauri::command]
-pub async fn get_current_music_tags() -> Result<Option<String>, String> {
+// The request Claude built for the current track (suno-config/suno_request.json)
+fn load_current_request() -> Result<crate::claude::HackmitGenerateReq, String> {
     let path = find_suno_config_file("suno_request.json")
         .ok_or_else(|| "Could not find suno-config/suno_request.json".to_string())?;
     let txt = std::fs::read_to_string(&path)
         .map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
-    let request: HackmitGenerateReq = serde_json::from_str(&txt)
-        .map_err(|e| format!("Invalid JSON in suno_request.json: {}", e))?;
-    Ok(request.tags)
+    serde_json::from_str(&txt).map_err(|e| format!("Invalid JSON in suno_request.json: {}", e))
 }
 
 #[tauri::command]
-pub async fn suno_hackmit_generate_and_wait() -> Result<String, String> {
-    let api_key = load_api_key().await?;
-    // Regenerate the request JSON via Claude using latest screenshot before generating
-    let generated = crate::claude::regenerate_suno_request_json().await
-        .map_err(|e| format!("Claude generation failed: {}", e))?;
-    let payload = generated; // Use freshly generated payload
-    let client = reqwest::Client::new();
+pub async fn get_current_music_tags() -> Result<Option<String>, String> {
+    Ok(load_current_request()?.tags)
+}
 
-    // 1) generate
-    let gen_res = client
-        .post(HACKMIT_GENERATE_URL)
-        .bearer_auth(&api_key)
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("HTTP error (generate): {}", e))?;
-    let status = gen_res.status();
-    let gen_text = gen_res.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() {
-        return Err(format!("Generate error ({}): {}", status, gen_text));
-    }
-    let gen: HackmitGenerateResp = serde_json::from_str(&gen_text)
-        .map_err(|e| format!("Parse generate response failed: {}. Raw: {}", e, gen_text))?;
+// A submitted task (generate or extend) counts toward the session's Suno credit estimate
+fn counted(submitted: Result<String, String>) -> Result<String, String> {
+    if submitted.is_ok() { crate::spend::record_suno_generation(); }
+    submitted
+}
 
-    // 2) poll clips until audio_url present
-    let max_iters = 36u32; // ~3 minutes @5s
-    for _ in 0..max_iters {
-        let url = format!("{}?ids={}", HACKMIT_CLIPS_URL, gen.id);
-        let clips_res = client
-            .get(url)
-            .bearer_auth(&api_key)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP error (clips): {}", e))?;
-        let st = clips_res.status();
-        let clips_text = clips_res.text().await.map_err(|e| e.to_string())?;
-        if !st.is_success() {
-            return Err(format!("Clips error ({}): {}", st, clips_text));
-        }
-        // The API can return either a top-level array or an object with { clips: [...] }
-    let clips: Vec<HackmitClip> = match serde_json::from_str::<Vec<HackmitClip>>(&clips_text) {
-            Ok(v) => v,
-            Err(_) => {
-                #[derive(Deserialize)]
-                struct Wrapper { clips: Vec<HackmitClip> }
-                let w: Wrapper = serde_json::from_str(&clips_text)
-                    .map_err(|e| format!("Parse clips response failed: {}. Raw: {}", e, clips_text))?;
-                w.clips
+// Command-facing error for a failed Claude step; refusals and low-confidence skips are also reported as events
+fn claude_failed(app: &tauri::AppHandle, what: &str, e: anyhow::Error) -> String {
+    crate::claude::report_refusal(app, &e, None);
+    crate::claude::report_low_confidence(app, &e, None);
+    format!("{}: {}", what, e)
+}
+
+#[tauri::command]
+pub async fn suno_hackmit_generate_and_wait(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>) -> Result<String, String> {
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        // Regenerate the request JSON via Claude using latest screenshot before generating
+        let generated = timed(&metrics, STAGE_CLAUDE, crate::claude::regenerate_suno_request_json(&client, None)).await
+            .map_err(|e| claude_failed(&app, "Claude generation failed", e))?;
+        timed(&metrics, STAGE_SUNO, hackmit_generate_and_wait(&client, &generated.request)).await
+    }).await
+}
+
+#[tauri::command]
+pub async fn suno_hackmit_generate_and_wait_with_prefs(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, prefs: crate::claude::FrontendPreferences) -> Result<String, String> {
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        // Regenerate the request JSON via Claude using latest screenshot and provided preferences
+        let generated = timed(&metrics, STAGE_CLAUDE, crate::claude::regenerate_suno_request_json_with_prefs(&client, prefs, None)).await
+            .map_err(|e| claude_failed(&app, "Claude generation failed", e))?;
+        timed(&metrics, STAGE_SUNO, hackmit_generate_and_wait(&client, &generated.request)).await
+    }).await
+}
+
+// Same music style as the last track, fresh lyrics from Claude
+#[tauri::command]
+pub async fn regenerate_lyrics(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>) -> Result<String, String> {
+    let prefs = fe_prefs.0.lock().await.clone();
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        let generated = timed(&metrics, STAGE_CLAUDE, crate::claude::regenerate_lyrics_only(&client, prefs)).await
+            .map_err(|e| claude_failed(&app, "Claude lyrics generation failed", e))?;
+        timed(&metrics, STAGE_SUNO, hackmit_generate_and_wait(&client, &generated)).await
+    }).await
+}
+
+// Like suno_hackmit_generate_and_wait (with the UI's stored preferences) but returns every take, not just the first
+#[tauri::command]
+pub async fn suno_hackmit_generate_clips(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>) -> Result<Vec<ClipResult>, String> {
+    let prefs = fe_prefs.0.lock().await.clone();
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        let api_key = load_api_key().await?;
+        let generated = timed(&metrics, STAGE_CLAUDE, async {
+            match prefs {
+                Some(prefs) => crate::claude::regenerate_suno_request_json_with_prefs(&client, prefs, None).await,
+                None => crate::claude::regenerate_suno_request_json(&client, None).await,
             }
-        };
-        // Find any clip with audio_url present
-        if let Some(url) = clips.iter().filter_map(|c| c.audio_url.clone()).next() {
-            return Ok(url);
-        }
-        sleep(std::time::Duration::from_secs(5)).await;
-    }
-    Err("Timed out waiting for audio URL".to_string())
+        }).await
+            .map_err(|e| claude_failed(&app, "Claude generation failed", e))?;
+        let clips = timed(&metrics, STAGE_SUNO, async {
+            let id = counted(hackmit_submit(&client, &hackmit_base_url(), &api_key, &generated.request).await)?;
+            hackmit_wait_for_clips(&client, &hackmit_base_url(), &api_key, &id, DEFAULT_POLL).await
+        }).await?;
+        Ok(clips.into_iter().map(ClipResult::from_clip).collect())
+    }).await
 }
 
+// Manual mode: send a hand-written request as-is, no screenshot or Claude involved
 #[tauri::command]
-pub async fn suno_hackmit_generate_and_wait_with_prefs(prefs: crate::claude::FrontendPreferences) -> Result<String, String> {
+pub async fn suno_generate_custom(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, req: crate::claude::HackmitGenerateReq) -> Result<ClipResult, String> {
+    req.validate()?;
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        // Becomes the current request, so tags/lyrics/extend commands follow it
+        let root = crate_root()?;
+        crate::claude::save_request(&root, &req).map_err(|e| e.to_string())?;
+        timed(&metrics, STAGE_SUNO, hackmit_generate_clip(&client, &req)).await
+    }).await
+}
+
+// Submit an already-built request to the HackMIT endpoint and poll until a clip has audio
+pub(crate) async fn hackmit_generate_and_wait(client: &reqwest::Client, payload: &crate::claude::HackmitGenerateReq) -> Result<String, String> {
+    hackmit_generate_clip(client, payload).await?.url.ok_or_else(|| "Clip has no audio URL".to_string())
+}
+
+// Same, keeping the clip's title/tags/duration
+pub(crate) async fn hackmit_generate_clip(client: &reqwest::Client, payload: &crate::claude::HackmitGenerateReq) -> Result<ClipResult, String> {
     let api_key = load_api_key().await?;
-    // Regenerate the request JSON via Claude using latest screenshot and provided preferences
-    let generated = crate::claude::regenerate_suno_request_json_with_prefs(prefs).await
-        .map_err(|e| format!("Claude generation failed: {}", e))?;
-    let payload = generated; // Use freshly generated payload
-    let client = reqwest::Client::new();
+    let base = hackmit_base_url();
+    let id = counted(hackmit_submit(client, &base, &api_key, payload).await)?;
+    let clip = hackmit_wait_for_clip(client, &base, &api_key, &id, DEFAULT_POLL).await?;
+    Ok(ClipResult::from_clip(clip))
+}
 
-    // 1) generate
-    let gen_res = client
-        .post(HACKMIT_GENERATE_URL)
-        .bearer_auth(&api_key)
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("HTTP error (generate): {}", e))?;
-    let status = gen_res.status();
-    let gen_text = gen_res.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() {
-        return Err(format!("Generate error ({}): {}", status, gen_text));
-    }
-    let gen: HackmitGenerateResp = serde_json::from_str(&gen_text)
-        .map_err(|e| format!("Parse generate response failed: {}. Raw: {}", e, gen_text))?;
+#[tauri::command]
+pub async fn suno_generate_from_latest_screenshot_with_prefs(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, prefs: crate::claude::FrontendPreferences) -> Result<TrackInfo, String> {
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        let api_key = load_api_key().await?;
+        let generated = timed(&metrics, STAGE_CLAUDE, crate::claude::regenerate_suno_request_json_with_prefs(&client, prefs, None)).await
+            .map_err(|e| claude_failed(&app, "Claude generation failed", e))?;
+        let clip = timed(&metrics, STAGE_SUNO, async {
+            let id = counted(hackmit_submit(&client, &hackmit_base_url(), &api_key, &generated.request).await)?;
+            hackmit_wait_for_clip(&client, &hackmit_base_url(), &api_key, &id, DEFAULT_POLL).await
+        }).await?;
+        Ok(TrackInfo {
+            id: Some(clip.id),
+            title: clip.title,
+            tags: None,
+            duration: None,
+            audio_url: clip.audio_url,
+            stream_audio_url: None,
+        })
+    }).await
+}
 
-    // 2) poll clips until audio_url present
-    let max_iters = 36u32; // ~3 minutes @5s
-    for _ in 0..max_iters {
-        let url = format!("{}?ids={}", HACKMIT_CLIPS_URL, gen.id);
-        let clips_res = client
-            .get(url)
-            .bearer_auth(&api_key)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP error (clips): {}", e))?;
-        let st = clips_res.status();
-        let clips_text = clips_res.text().await.map_err(|e| e.to_string())?;
-        if !st.is_success() {
-            return Err(format!("Clips error ({}): {}", st, clips_text));
-        }
-        let clips: Vec<HackmitClip> = match serde_json::from_str::<Vec<HackmitClip>>(&clips_text) {
-            Ok(v) => v,
-            Err(_) => {
-                #[derive(Deserialize)]
-                struct Wrapper { clips: Vec<HackmitClip> }
-                let w: Wrapper = serde_json::from_str(&clips_text)
-                    .map_err(|e| format!("Parse clips response failed: {}. Raw: {}", e, clips_text))?;
-                w.clips
-            }
+#[tauri::command]
+pub async fn suno_generate_track_and_wait(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>, prefer: Option<UrlPreference>) -> Result<TrackResult, String> {
+    let fe = fe_prefs.0.lock().await.clone();
+    let prefer = prefer.unwrap_or_default();
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        let api_key = load_api_key().await?;
+        let mut payload = load_request().await?;
+        payload.model = resolve_model(fe.as_ref(), payload.model)?;
+        payload.vocal_gender = resolve_vocal_gender(fe.as_ref(), &payload)?;
+        let track = timed(&metrics, STAGE_SUNO, async {
+            let task_id = counted(submit_generate(&client, &suno_base_url(), &api_key, &payload).await)?;
+            wait_for_track(&client, &suno_base_url(), &api_key, &task_id, DEFAULT_POLL, prefer).await
+        }).await?;
+        TrackResult::from_track(track, prefer).ok_or_else(|| "Track has no playable URL".to_string())
+    }).await
+}
+
+// Build request.json from the latest screenshot with Claude and the UI's preferences, for the main API
+// commands (suno_generate_track_and_wait, suno_generate_and_wait) to submit
+#[tauri::command]
+pub async fn regenerate_request_json(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>) -> Result<GenerateRequest, String> {
+    let prefs = fe_prefs.0.lock().await.clone().unwrap_or_default();
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        timed(&metrics, STAGE_CLAUDE, crate::claude::regenerate_request_json_with_prefs(&client, prefs, None)).await
+            .map_err(|e| claude_failed(&app, "Claude generation failed", e))
+    }).await
+}
+
+// Deprecated: URL-only variant kept for older frontends (which play it), use suno_generate_track_and_wait
+#[tauri::command]
+pub async fn suno_generate_and_wait(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>) -> Result<String, String> {
+    suno_generate_track_and_wait(app, client, control, metrics, fe_prefs, Some(UrlPreference::Stream)).await.map(|t| t.url)
+}
+
+// Lengthen a clip, keeping the current request's style and lyrics; model and callback come from request.json
+#[tauri::command]
+pub async fn suno_extend_clip(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, control: tauri::State<'_, PipelineControl>, metrics: tauri::State<'_, Metrics>, fe_prefs: tauri::State<'_, crate::claude::FrontendPrefsState>, clip_id: String, continue_at: Option<f32>, prefer: Option<UrlPreference>) -> Result<TrackResult, String> {
+    let fe = fe_prefs.0.lock().await.clone();
+    let prefer = prefer.unwrap_or_default();
+    unless_stopped(&control, async {
+        let _slot = generation_slot(&app, &control).await?;
+        let api_key = load_api_key().await?;
+        let path = find_suno_config_file("request.json")
+            .ok_or_else(|| "Could not find suno-config/request.json (needed for model and callBackUrl)".to_string())?;
+        let txt = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
+        let base: GenerateRequest = serde_json::from_str(&txt)
+            .map_err(|e| format!("Invalid JSON in request.json: {}", e))?;
+        let current = load_current_request()?;
+        let payload = ExtendRequest {
+            default_param_flag: true,
+            audio_id: clip_id,
+            prompt: current.prompt.or(current.topic),
+            style: current.tags,
+            title: base.title,
+            continue_at,
+            model: resolve_model(fe.as_ref(), base.model)?,
+            callback_url: base.callback_url,
         };
-        if let Some(url) = clips.iter().filter_map(|c| c.audio_url.clone()).next() {
-            return Ok(url);
-        }
-        sleep(std::time::Duration::from_secs(5)).await;
-    }
-    Err("Timed out waiting for audio URL".to_string())
+        let track = timed(&metrics, STAGE_SUNO, async {
+            let task_id = counted(submit_task(&client, &suno_base_url(), &api_key, SUNO_EXTEND_PATH, &payload).await)?;
+            wait_for_track(&client, &suno_base_url(), &api_key, &task_id, DEFAULT_POLL, prefer).await
+        }).await?;
+        TrackResult::from_track(track, prefer).ok_or_else(|| "Extended track has no playable URL".to_string())
+    }).await
 }
 
+// Download a clip into temp/audio and return a http://127.0.0.1 URL the webview can always play and seek,
+// whatever the remote URL's CORS headers or expiry. Downloading the same `clip_id` again overwrites it.
 #[tauri::command]
-pub async fn suno_generate_from_latest_screenshot_with_prefs(prefs: crate::claude::FrontendPreferences) -> Result<TrackInfo, String> {
-    let api_key = load_api_key().await?;
-    let generated = crate::claude::regenerate_suno_request_json_with_prefs(prefs).await
-        .map_err(|e| format!("Claude generation failed: {}", e))?;
-    let client = reqwest::Client::new();
+pub async fn suno_download_clip(app: tauri::AppHandle, client: tauri::State<'_, reqwest::Client>, url: String, clip_id: Option<String>) -> Result<String, String> {
+    download_clip(&app, &client, &url, clip_id.as_deref()).await
+}
 
-    let gen_res = client
-        .post(HACKMIT_GENERATE_URL)
-        .bearer_auth(&api_key)
-        .json(&generated)
+// Shared by the command and headless mode; returns the local server URL
+pub(crate) async fn download_clip(app: &tauri::AppHandle, client: &reqwest::Client, url: &str, clip_id: Option<&str>) -> Result<String, String> {
+    use tauri::Manager;
+    let server = app.try_state::<crate::audio_server::AudioServer>().ok_or_else(|| "Local audio server is not running".to_string())?;
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid clip URL: {}", e))?;
+    let remote_name = parsed.path_segments().and_then(|mut s| s.next_back()).unwrap_or("");
+    let ext = std::path::Path::new(remote_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| crate::audio_server::is_safe_name(e))
+        .unwrap_or("mp3");
+    let stem = clip_id.or_else(|| remote_name.split('.').next()).unwrap_or("");
+    let name = format!("{}.{}", stem, ext);
+    if !crate::audio_server::is_safe_name(stem) {
+        return Err(format!("Can't use '{}' as a file name; pass a clip_id", name));
+    }
+
+    let res = client.get(parsed).send().await.map_err(|e| format!("Download failed: {}", e))?;
+    let status = res.status();
+    if !status.is_success() { return Err(format!("Download failed ({})", status)); }
+    let bytes = res.bytes().await.map_err(|e| format!("Download failed: {}", e))?;
+    let dir = server.dir().to_path_buf();
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    // Write then rename so the server never serves a half-written file
+    let partial = dir.join(format!(".{}.part", name));
+    tokio::fs::write(&partial, &bytes).await.map_err(|e| format!("Failed to save clip: {}", e))?;
+    tokio::fs::rename(&partial, dir.join(&name)).await.map_err(|e| format!("Failed to save clip: {}", e))?;
+    Ok(server.url_for(&name))
+}
+
+// Core Suno calls below take their client, host (suno_base_url / hackmit_base_url) and key as arguments
+// and never touch Tauri, the environment or the filesystem
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PollSettings {
+    pub interval: std::time::Duration,
+    pub max_iters: u32,
+}
+
+pub(crate) const DEFAULT_POLL: PollSettings = PollSettings {
+    interval: std::time::Duration::from_secs(5),
+    max_iters: 36, // ~3 minutes @5s
+};
+
+// Submit to the main Suno API and return the task id
+pub(crate) async fn submit_generate(client: &reqwest::Client, base: &str, api_key: &str, payload: &GenerateRequest) -> Result<String, String> {
+    submit_task(client, base, api_key, SUNO_API_PATH, payload).await
+}
+
+// POST a task-creating request (generate, extend) and return the task id to poll with wait_for_track
+async fn submit_task(client: &reqwest::Client, base: &str, api_key: &str, path: &str, payload: &impl Serialize) -> Result<String, String> {
+    let res = client
+        .post(format!("{}{}", base, path))
+        .bearer_auth(api_key)
+        .json(payload)
         .send()
         .await
-        .map_err(|e| format!("HTTP error (generate): {}", e))?;
-    let status = gen_res.status();
-    let gen_text = gen_res.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() { return Err(format!("Generate error ({}): {}", status, gen_text)); }
-    let gen: HackmitGenerateResp = serde_json::from_str(&gen_text)
-        .map_err(|e| format!("Parse generate response failed: {}. Raw: {}", e, gen_text))?;
+        .map_err(|e| format!("HTTP error: {}", e))?;
+    let status = res.status();
+    let text = res.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(http_error("Suno API error", SUNO_API_NAME, status, &text));
+    }
+    let parsed: GenerateResponse = serde_json::from_str(&text)
+        .map_err(|e| format!("Failed to parse response: {}. Raw: {}", e, text))?;
+    if parsed.code != 200 {
+        return Err(format!("Suno API returned code {}: {}", parsed.code, parsed.msg));
+    }
+    parsed
+        .data
+        .map(|d| d.task_id)
+        .ok_or_else(|| "Missing data in response".to_string())
+}
 
-    // Poll short for first available clip url
-    let max_iters = 36u32;
-    for _ in 0..max_iters {
-        let url = format!("{}?ids={}", HACKMIT_CLIPS_URL, gen.id);
-        let clips_res = client.get(url).bearer_auth(&api_key).send().await
-            .map_err(|e| format!("HTTP error (clips): {}", e))?;
-        let st = clips_res.status();
-        let clips_text = clips_res.text().await.map_err(|e| e.to_string())?;
-        if !st.is_success() { return Err(format!("Clips error ({}): {}", st, clips_text)); }
-        let mut clips: Vec<HackmitClip> = match serde_json::from_str::<Vec<HackmitClip>>(&clips_text) {
-            Ok(v) => v,
-            Err(_) => {
-                #[derive(Deserialize)]
-                struct Wrapper { clips: Vec<HackmitClip> }
-                let w: Wrapper = serde_json::from_str(&clips_text)
-                    .map_err(|e| format!("Parse clips response failed: {}. Raw: {}", e, clips_text))?;
-                w.clips
-            }
-        };
-        if let Some(clip) = clips.pop() {
-            if let Some(url) = clip.audio_url.clone() {
-                return Ok(TrackInfo {
-                    id: Some(clip.id),
-                    title: clip.title.clone(),
-                    tags: None,
-                    duration: None,
-                    audio_url: Some(url.clone()),
-                    stream_audio_url: None,
-                });
-            }
-        }
-        sleep(std::time::Duration::from_secs(5)).await;
+pub(crate) async fn fetch_credits(client: &reqwest::Client, base: &str, api_key: &str) -> Result<i64, String> {
+    let res = client
+        .get(format!("{}{}", base, SUNO_CREDITS_PATH))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .map_err(|e| format!("HTTP error: {}", e))?;
+    let status = res.status();
+    let text = res.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(http_error("Credits API error", SUNO_API_NAME, status, &text));
     }
-    Err("Timed out waiting for audio URL".to_string())
+    let parsed: CreditsResponse = serde_json::from_str(&text)
+        .map_err(|e| format!("Failed to parse credits response: {}. Raw: {}", e, text))?;
+    if parsed.code != 200 {
+        return Err(format!("Suno API returned code {}: {}", parsed.code, parsed.msg));
+    }
+    Ok(parsed.data.and_then(|d| d.credits).unwrap_or(0))
 }
 
-async fn get_status(client: &reqwest::Client, api_key: &str, task_id: &str) -> Result<StatusResponse, String> {
-    let url = format!("{}?taskId={}", SUNO_STATUS_URL, task_id);
+async fn get_status(client: &reqwest::Client, base: &str, api_key: &str, task_id: &str) -> Result<StatusResponse, String> {
+    let url = format!("{}{}?taskId={}", base, SUNO_STATUS_PATH, task_id);
     let res = client
         .get(url)
         .bearer_auth(api_key)
@@ -438,70 +754,278 @@ async fn get_status(client: &reqwest::Client, api_key: &str, task_id: &str) -> R
     let status = res.status();
     let text = res.text().await.map_err(|e| e.to_string())?;
     if !status.is_success() {
-        return Err(format!("Status API error ({}): {}", status, text));
+        return Err(http_error("Status API error", SUNO_API_NAME, status, &text));
     }
     serde_json::from_str::<StatusResponse>(&text)
         .map_err(|e| format!("Failed to parse status response: {}. Raw: {}", e, text))
 }
 
-fn pick_stream_or_audio(tracks: &[TrackInfo]) -> Option<String> {
-    // Prefer stream URL; fall back to audio_url
+// First track with a URL the caller can use: any for streaming, the finished file for downloads
+fn pick_stream_or_audio(tracks: &[TrackInfo], prefer: UrlPreference) -> Option<TrackInfo> {
     tracks
         .iter()
-        .filter_map(|t| t.stream_audio_url.clone().or_else(|| t.audio_url.clone()))
-        .next()
+        .find(|t| match prefer {
+            UrlPreference::Stream => t.stream_audio_url.is_some() || t.audio_url.is_some(),
+            UrlPreference::Download => t.audio_url.is_some(),
+        })
+        .cloned()
 }
 
-#[tauri::command]
-pub async fn suno_generate_and_wait() -> Result<String, String> {
-    let api_key = load_api_key().await?;
-    let payload = load_request().await?;
+// FAILED, CREATE_TASK_FAILED, GENERATE_AUDIO_FAILED, SENSITIVE_WORD_ERROR, CALLBACK_EXCEPTION, ...
+fn is_failed_status(status: &str) -> bool {
+    let s = status.to_ascii_uppercase();
+    s.ends_with("FAILED") || s.ends_with("ERROR") || s.ends_with("EXCEPTION")
+}
 
-    let client = reqwest::Client::new();
-    let res = client
-        .post(SUNO_API_URL)
-        .bearer_auth(&api_key)
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("HTTP error: {}", e))?;
-    let status = res.status();
-    let text = res.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() {
-        return Err(format!("Suno API error ({}): {}", status, text));
+// Final success only; TEXT_SUCCESS / FIRST_SUCCESS still have audio on the way
+fn is_complete_status(status: &str) -> bool {
+    matches!(status.to_ascii_uppercase().as_str(), "SUCCESS" | "COMPLETE" | "COMPLETED")
+}
+
+// One poll's verdict: Ok(Some) = playable track, Ok(None) = still processing, Err = give up now
+fn track_from_status(data: StatusData, prefer: UrlPreference) -> Result<Option<TrackInfo>, String> {
+    let status = data.status.unwrap_or_default();
+    if is_failed_status(&status) {
+        return Err(format!("Suno generation failed ({})", status));
     }
-    let parsed: GenerateResponse = serde_json::from_str(&text)
-        .map_err(|e| format!("Failed to parse response: {}. Raw: {}", e, text))?;
-    if parsed.code != 200 {
-        return Err(format!("Suno API returned code {}: {}", parsed.code, parsed.msg));
+    let tracks = data.response.and_then(|r| r.data).unwrap_or_default();
+    if let Some(track) = pick_stream_or_audio(&tracks, prefer) {
+        return Ok(Some(track));
     }
-    let task_id = parsed
-        .data
-        .ok_or_else(|| "Missing data in response".to_string())?
-        .task_id;
-
-    // Poll for up to ~3 minutes; check every 5 seconds
-    let max_iters = 36u32; // 36 * 5s = 180s
-    for _ in 0..max_iters {
-        let status = get_status(&client, &api_key, &task_id).await?;
-        if status.code != 200 {
-            // Keep trying unless explicit failure can be inferred
-        }
+    if is_complete_status(&status) {
+        // Completed with nothing to play won't change by polling longer
+        return Err(format!("Suno reported {} but returned no playable tracks ({} in response)", status, tracks.len()));
+    }
+    Ok(None)
+}
+
+// Poll record-info until a track has a playable URL, the task fails, or we run out of attempts
+pub(crate) async fn wait_for_track(client: &reqwest::Client, base: &str, api_key: &str, task_id: &str, poll: PollSettings, prefer: UrlPreference) -> Result<TrackInfo, String> {
+    for _ in 0..poll.max_iters {
+        let status = get_status(client, base, api_key, task_id).await?;
         if let Some(data) = status.data {
-            if let Some(ref s) = data.status {
-                if s.eq_ignore_ascii_case("FAILED") { 
-                    return Err("Suno generation failed".to_string());
-                }
-            }
-            if let Some(resp) = data.response {
-                if let Some(tracks) = resp.data {
-                    if let Some(url) = pick_stream_or_audio(&tracks) {
-                        return Ok(url);
-                    }
-                }
+            if let Some(track) = track_from_status(data, prefer)? {
+                return Ok(track);
             }
         }
-    sleep(std::time::Duration::from_secs(5)).await;
+        sleep(poll.interval).await;
     }
     Err("Timed out waiting for stream URL".to_string())
 }
+
+// Submit to the HackMIT endpoint and return the request id to poll
+pub(crate) async fn hackmit_submit(client: &reqwest::Client, base: &str, api_key: &str, payload: &crate::claude::HackmitGenerateReq) -> Result<String, String> {
+    let gen_res = client
+        .post(format!("{}{}", base, HACKMIT_GENERATE_PATH))
+        .bearer_auth(api_key)
+        .json(&HackmitGenerateReq::from(payload))
+        .send()
+        .await
+        .map_err(|e| format!("HTTP error (generate): {}", e))?;
+    let status = gen_res.status();
+    let gen_text = gen_res.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(http_error("Generate error", HACKMIT_API_NAME, status, &gen_text));
+    }
+    let gen: HackmitGenerateResp = serde_json::from_str(&gen_text)
+        .map_err(|e| format!("Parse generate response failed: {}. Raw: {}", e, gen_text))?;
+    Ok(gen.id)
+}
+
+// The API can return either a top-level array or an object with { clips: [...] }
+fn parse_clips(clips_text: &str) -> Result<Vec<HackmitClip>, String> {
+    if let Ok(v) = serde_json::from_str::<Vec<HackmitClip>>(clips_text) {
+        return Ok(v);
+    }
+    #[derive(Deserialize)]
+    struct Wrapper { clips: Vec<HackmitClip> }
+    let w: Wrapper = serde_json::from_str(clips_text)
+        .map_err(|e| format!("Parse clips response failed: {}. Raw: {}", e, clips_text))?;
+    Ok(w.clips)
+}
+
+// Poll HackMIT clips until any clip has an audio_url
+pub(crate) async fn hackmit_wait_for_clip(client: &reqwest::Client, base: &str, api_key: &str, id: &str, poll: PollSettings) -> Result<HackmitClip, String> {
+    hackmit_wait_for_clips(client, base, api_key, id, poll)
+        .await?
+        .into_iter()
+        .find(|c| c.audio_url.is_some())
+        .ok_or_else(|| "Timed out waiting for audio URL".to_string())
+}
+
+// One look at the clips of a request, whatever state they're in
+async fn hackmit_fetch_clips(client: &reqwest::Client, base: &str, api_key: &str, id: &str) -> Result<Vec<HackmitClip>, String> {
+    let url = format!("{}{}?ids={}", base, HACKMIT_CLIPS_PATH, id);
+    let clips_res = client
+        .get(url)
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .map_err(|e| format!("HTTP error (clips): {}", e))?;
+    let st = clips_res.status();
+    let clips_text = clips_res.text().await.map_err(|e| e.to_string())?;
+    if !st.is_success() {
+        return Err(http_error("Clips error", HACKMIT_API_NAME, st, &clips_text));
+    }
+    parse_clips(&clips_text)
+}
+
+// All clips of a request, returned as soon as any of them has an audio_url
+pub(crate) async fn hackmit_wait_for_clips(client: &reqwest::Client, base: &str, api_key: &str, id: &str, poll: PollSettings) -> Result<Vec<HackmitClip>, String> {
+    for _ in 0..poll.max_iters {
+        let clips = hackmit_fetch_clips(client, base, api_key, id).await?;
+        if clips.iter().any(|c| c.audio_url.is_some()) {
+            return Ok(clips);
+        }
+        sleep(poll.interval).await;
+    }
+    Err("Timed out waiting for audio URL".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn status_data(value: serde_json::Value) -> StatusData {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn track_from_status_success_without_tracks_is_an_error() {
+        let data = status_data(json!({ "taskId": "t1", "status": "SUCCESS", "response": { "data": [] } }));
+        let err = track_from_status(data, UrlPreference::Stream).unwrap_err();
+        assert!(err.contains("no playable tracks"), "{}", err);
+    }
+
+    #[test]
+    fn track_from_status_pending_without_tracks_keeps_polling() {
+        let data = status_data(json!({ "taskId": "t1", "status": "PENDING", "response": { "data": [] } }));
+        assert!(track_from_status(data, UrlPreference::Stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn track_from_status_null_response_keeps_polling() {
+        let data = status_data(json!({ "taskId": "t1", "status": "TEXT_SUCCESS", "response": null }));
+        assert!(track_from_status(data, UrlPreference::Stream).unwrap().is_none());
+        let data = status_data(json!({ "taskId": "t1", "status": "PENDING", "response": { "data": null } }));
+        assert!(track_from_status(data, UrlPreference::Stream).unwrap().is_none());
+    }
+
+    const FAST_POLL: PollSettings = PollSettings { interval: std::time::Duration::from_millis(1), max_iters: 2 };
+
+    fn status_body(status: &str, tracks: serde_json::Value) -> serde_json::Value {
+        json!({ "code": 200, "msg": "success", "data": { "taskId": "t1", "status": status, "response": { "data": tracks } } })
+    }
+
+    async fn mock_status(server: &MockServer, body: serde_json::Value) {
+        Mock::given(method("GET"))
+            .and(path(SUNO_STATUS_PATH))
+            .and(query_param("taskId", "t1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn wait_for_track_returns_the_finished_track() {
+        let server = MockServer::start().await;
+        let track = json!([{ "id": "a", "title": "Focus", "audio_url": "https://cdn/a.mp3", "stream_audio_url": null }]);
+        mock_status(&server, status_body("SUCCESS", track)).await;
+        let client = reqwest::Client::new();
+        let got = wait_for_track(&client, &server.uri(), "key", "t1", FAST_POLL, UrlPreference::Download).await.unwrap();
+        assert_eq!(got.audio_url.as_deref(), Some("https://cdn/a.mp3"));
+        assert_eq!(got.title.as_deref(), Some("Focus"));
+    }
+
+    #[tokio::test]
+    async fn wait_for_track_stops_on_failed() {
+        let server = MockServer::start().await;
+        mock_status(&server, status_body("FAILED", json!([]))).await;
+        let client = reqwest::Client::new();
+        let err = wait_for_track(&client, &server.uri(), "key", "t1", FAST_POLL, UrlPreference::Stream).await.unwrap_err();
+        assert!(err.contains("FAILED"), "{}", err);
+        // Gave up on the first poll instead of polling on
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_for_track_times_out_while_pending() {
+        let server = MockServer::start().await;
+        mock_status(&server, status_body("PENDING", json!([]))).await;
+        let client = reqwest::Client::new();
+        let err = wait_for_track(&client, &server.uri(), "key", "t1", FAST_POLL, UrlPreference::Stream).await.unwrap_err();
+        assert!(err.contains("Timed out"), "{}", err);
+        assert_eq!(server.received_requests().await.unwrap().len(), FAST_POLL.max_iters as usize);
+    }
+
+    async fn mock_clips(server: &MockServer, body: serde_json::Value) {
+        Mock::given(method("GET"))
+            .and(path(HACKMIT_CLIPS_PATH))
+            .and(query_param("ids", "r1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn hackmit_wait_for_clips_returns_once_audio_is_ready() {
+        let server = MockServer::start().await;
+        mock_clips(&server, json!([
+            { "id": "c1", "status": "complete", "audio_url": "https://cdn/c1.mp3" },
+            { "id": "c2", "status": "streaming", "audio_url": null },
+        ]))
+        .await;
+        let client = reqwest::Client::new();
+        let clips = hackmit_wait_for_clips(&client, &server.uri(), "key", "r1", FAST_POLL).await.unwrap();
+        assert_eq!(clips.len(), 2);
+        assert_eq!(clips[0].audio_url.as_deref(), Some("https://cdn/c1.mp3"));
+    }
+
+    #[tokio::test]
+    async fn hackmit_wait_for_clips_times_out_without_audio() {
+        let server = MockServer::start().await;
+        mock_clips(&server, json!({ "clips": [{ "id": "c1", "status": "submitted", "audio_url": null }] })).await;
+        let client = reqwest::Client::new();
+        let err = hackmit_wait_for_clips(&client, &server.uri(), "key", "r1", FAST_POLL).await.unwrap_err();
+        assert!(err.contains("Timed out"), "{}", err);
+        assert_eq!(server.received_requests().await.unwrap().len(), FAST_POLL.max_iters as usize);
+    }
+
+    #[tokio::test]
+    async fn hackmit_wait_for_clips_surfaces_http_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(HACKMIT_CLIPS_PATH))
+            .respond_with(ResponseTemplate::new(500).set_body_string("boom"))
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::new();
+        assert!(hackmit_wait_for_clips(&client, &server.uri(), "key", "r1", FAST_POLL).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn hackmit_submit_leaves_negative_tags_out_of_the_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(HACKMIT_GENERATE_PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "req-1" })))
+            .mount(&server)
+            .await;
+        let payload = crate::claude::HackmitGenerateReq {
+            topic: Some("Calm focus".to_string()),
+            tags: Some("lofi, ambient".to_string()),
+            negative_tags: Some("elevator music".to_string()),
+            make_instrumental: Some(true),
+            ..Default::default()
+        };
+        let id = hackmit_submit(&reqwest::Client::new(), &server.uri(), "key", &payload).await.unwrap();
+        assert_eq!(id, "req-1");
+        let body: serde_json::Value = server.received_requests().await.unwrap()[0].body_json().unwrap();
+        assert_eq!(body["topic"], "Calm focus");
+        assert_eq!(body["make_instrumental"], true);
+        assert!(body.get("negative_tags").is_none() && body.get("negativeTags").is_none(), "{}", body);
+    }
+}
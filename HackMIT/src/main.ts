@@ -8,6 +8,7 @@ let audioEl: HTMLAudioElement | null;
 let nextUrl: string | null = null;
 let contextEl: HTMLElement | null;
 let generating = false;
+let lastFadeMs = 1500; // from the latest switch decision
 let history: string[] = []; // played track URLs (for Back)
 
 const getButtonText = (button: HTMLButtonElement, active: boolean): string => {
@@ -124,16 +125,18 @@ window.addEventListener("DOMContentLoaded", () => {
 
     async function generateTrack(): Promise<string> {
         const prefs = collectPreferences();
+        // Keep the backend's automatic loop in sync with the UI controls
+        invoke("set_frontend_preferences", { prefs }).catch(() => {});
         return await invoke<string>("suno_hackmit_generate_and_wait_with_prefs", { prefs });
     }
 
-    async function fadeOutAndSwitch(newUrl: string) {
+    async function fadeOutAndSwitch(newUrl: string, fadeMs = 1000) {
         if (!audioEl) return;
     // Save current to history before switching
     if (audioEl.src) { history.push(audioEl.src); }
         const startVol = audioEl.volume;
         const steps = 10;
-        const intervalMs = 100;
+        const intervalMs = fadeMs / steps;
         for (let i = 0; i < steps; i++) {
             audioEl.volume = Math.max(0, startVol * (1 - (i + 1) / steps));
             await new Promise((r) => setTimeout(r, intervalMs));
@@ -222,12 +225,20 @@ window.addEventListener("DOMContentLoaded", () => {
             const payload: any = (ev as any).payload;
             const action = payload?.action as string | undefined;
             if (!audioEl || !action) return;
+            if (action === "stopped") { audioEl.pause(); return; }
+            // Follow-up to a decision already handled: what the switch turned into. Its track comes via music:switch.
+            if (action === "generated") return;
+            // Backend sizes the crossfade by how big the context change was
+            if (typeof payload?.fade_ms === "number") { lastFadeMs = payload.fade_ms; }
             // Show context in UI
             const ctx = payload?.current_context;
             const prev = payload?.previous_context;
             if (contextEl && ctx) {
                 const prevTag = prev?.tag ? ` (prev: ${prev.tag})` : "";
-                let contextText = `Context: ${ctx.tag} — ${ctx.details}${prevTag}`;
+                const blended = action === "blend" ? payload?.blended_context : null;
+                let contextText = blended?.tag
+                    ? `Context: ${ctx.tag} + ${blended.tag} (blend) — ${ctx.details}`
+                    : `Context: ${ctx.tag} — ${ctx.details}${prevTag}`;
                 
                 // Add music tags to context display
                 try {
@@ -246,14 +257,17 @@ window.addEventListener("DOMContentLoaded", () => {
                 (async () => {
                     try {
                         const url = await generateTrack(); // invokes backend which regenerates suno_request.json from latest screenshot
-                        await fadeOutAndSwitch(url);
+                        await fadeOutAndSwitch(url, lastFadeMs);
                         // Optionally warm a next track without blocking
                         generateTrack().then((nu) => { nextUrl = nu; pushLog("prefetched next after fade switch"); }).catch(() => {});
                     } catch (e) {
                         pushLog(`priority generation failed: ${e}`);
                     }
                 })();
-            } else {
+            } else if (action === "blend") {
+                // The backend generates one track for both contexts and sends it via music:switch; no separate prefetch
+                pushLog(`blending ${ctx?.tag} with ${payload?.blended_context?.tag}`);
+            } else if (action !== "observe") {
                 // continue: ensure we have a next track ready
                 if (!nextUrl && !generating) {
                     generating = true;
@@ -268,7 +282,7 @@ window.addEventListener("DOMContentLoaded", () => {
                 const audioUrl = ev.payload as string;
                 if (audioUrl && audioEl) {
                     console.log("Received music:switch, immediately switching to:", audioUrl);
-                    await fadeOutAndSwitch(audioUrl);
+                    await fadeOutAndSwitch(audioUrl, lastFadeMs);
                     
                     // Update context display with new music tags
                     if (contextEl) {